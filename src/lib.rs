@@ -4,49 +4,1166 @@
 //! - POST /auth/token
 //! - GET /v2/DataService             (table option endpoint: 'DataServices')
 //! - GET /v2/FieldMappingDefinitions (table option endpoint: 'FieldMappingDefinitions')
+//! - (no request)                    (table option endpoint: 'Options', lists supported options)
+//! - POST /auth/token                (table option endpoint: 'Health', one-row monitoring probe)
 //!
 //! Notes:
 //! - Token is cached in-memory for best-effort performance.
 //! - If a request returns 401/403, the token is refreshed once and retried.
-//! - DataServices supports paging via Skip/Take.
+//! - DataServices supports paging via Skip/Take (default), keyset, continuation token,
+//!   or a single request; see the `Pager` trait for how strategies plug in.
 //! - Take defaults to 10 and clamps to <= 10 (or uses <10 if provided, per your requested rule).
 //!
 //! IMPORTANT:
 //! - This version reads `client_id` and `client_secret` from **server options**.
 
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
 use serde::Deserialize;
 use serde_json::Value as JsonValue;
 use urlencoding::encode;
 use supabase_wrappers::prelude::*;
 
+const FDW_NAME: &str = "SustainalyticsFdw";
 const DEFAULT_BASE_URL: &str = "https://api.sustainalytics.com";
-const DEFAULT_TAKE: i64 = 10;
 const MAX_TAKE: i64 = 10;
+/// Upper bound for a `totalCount` reported by the API; anything above is
+/// treated as corrupted metadata rather than paged through.
+const MAX_TOTAL_ROWS: i64 = 100_000_000;
+/// Nesting kept in a definition's `parentage`; deeper levels become null.
+const MAX_PARENTAGE_DEPTH: usize = 32;
+/// serde_json's nesting limit; deeper responses can't be parsed at all.
+const MAX_JSON_DEPTH: usize = 128;
+/// Upper bound on the dates of one `AsOf_start`..`AsOf_end` backfill.
+const MAX_ASOF_DATES: usize = 10_000;
+/// Response header (or envelope attribute `catalogVersion`) announcing the
+/// FieldMappingDefinitions version.
+const CATALOG_VERSION_HEADER: &str = "x-catalog-version";
+/// Catalog refreshes after the first back off from this, doubling up to
+/// `MAX_CATALOG_BACKOFF_SECS`, so a version flapping between gateway nodes
+/// during a rollout doesn't reload the catalog on every page.
+const CATALOG_BACKOFF_SECS: i64 = 30;
+const MAX_CATALOG_BACKOFF_SECS: i64 = 3600;
+/// Response header with the hex SHA-256 of the body, checked under `verify_responses`.
+const CONTENT_SHA256_HEADER: &str = "x-content-sha256";
+/// Retries of a response failing `verify_responses` before the request fails.
+const MAX_VERIFY_RETRIES: u32 = 2;
+/// How long an `available_endpoints = 'probe'` result is reused from the stats metadata.
+const CAPABILITY_TTL_SECS: i64 = 86_400;
+/// A cached token this close to expiry is replaced before use.
+const TOKEN_EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// Where an option is declared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OptionScope {
+    Server,
+    Table,
+}
+
+impl OptionScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            OptionScope::Server => "server",
+            OptionScope::Table => "table",
+        }
+    }
+}
+
+/// Value type of an option, used both to parse it and to document it.
+#[derive(Debug, Clone, Copy)]
+enum OptionKind {
+    Text,
+    Bool,
+    /// Integer within an inclusive range.
+    Int { min: i64, max: i64 },
+    /// One of a fixed set of values.
+    Choice(&'static [&'static str]),
+    /// Comma-separated integer ids.
+    IdList,
+}
+
+impl OptionKind {
+    fn type_name(self) -> String {
+        match self {
+            OptionKind::Text => "text".to_string(),
+            OptionKind::Bool => "boolean".to_string(),
+            OptionKind::Int { min, max } if max == i64::MAX => format!("integer (>= {})", min),
+            OptionKind::Int { min, max } => format!("integer ({}-{})", min, max),
+            OptionKind::Choice(values) => format!("text ({})", values.join(" | ")),
+            OptionKind::IdList => "id list".to_string(),
+        }
+    }
+
+    fn validate(self, name: &str, value: &str) -> FdwResult<()> {
+        match self {
+            OptionKind::Text => Ok(()),
+            OptionKind::Bool => match value {
+                "true" | "false" => Ok(()),
+                _ => Err(format!("invalid {}: {} (expected true or false)", name, value).into()),
+            },
+            OptionKind::Int { min, max } => {
+                let n = value
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| format!("invalid {}: {} (expected an integer)", name, value))?;
+                if n < min || n > max {
+                    return Err(format!("invalid {}: {} (expected {})", name, n, self.type_name()).into());
+                }
+                Ok(())
+            }
+            OptionKind::Choice(values) => {
+                if values.contains(&value) {
+                    Ok(())
+                } else {
+                    Err(format!("invalid {}: {} (expected one of {})", name, value, values.join(", ")).into())
+                }
+            }
+            OptionKind::IdList => {
+                let bad: Vec<&str> = value
+                    .split(',')
+                    .map(|id| id.trim())
+                    .filter(|id| id.parse::<i64>().is_err())
+                    .collect();
+                if bad.is_empty() {
+                    Ok(())
+                } else {
+                    Err(format!("invalid {}: non-numeric ids [{}]", name, bad.join(", ")).into())
+                }
+            }
+        }
+    }
+}
+
+/// One supported option: how to parse it, its default, and its documentation.
+/// Parsing (`TypedOptions`) and the `Options` endpoint both read from here.
+#[derive(Debug, Clone, Copy)]
+struct OptionSpec {
+    scope: OptionScope,
+    name: &'static str,
+    /// Endpoints whose tables accept the option; empty for server options.
+    endpoints: &'static [&'static str],
+    kind: OptionKind,
+    default: Option<&'static str>,
+    description: &'static str,
+}
+
+const PAGING_MODES: &[&str] = &["skip_take", "keyset", "token", "single"];
+const REQUEST_MODES: &[&str] = &["paged", "per_entity"];
+const DUPLICATE_FIELDS: &[&str] = &["first", "last", "error", "long"];
+const VALUE_CHECKS: &[&str] = &["off", "warn", "null"];
+const SUMMARY_ROWS: &[&str] = &["false", "true", "notice"];
+const ENDPOINTS: &[&str] = &["DataServices", "FieldMappingDefinitions", "Options", "Health"];
+const DATA_SERVICES: &[&str] = &["DataServices"];
+/// Endpoints serving API data (as opposed to the Options/Health pseudo tables).
+const DATA_ENDPOINTS: &[&str] = &["DataServices", "FieldMappingDefinitions"];
+/// API path behind each data endpoint, requested by the capability probe.
+const ENDPOINT_PATHS: &[(&str, &str)] = &[("DataServices", "/v2/DataService"), ("FieldMappingDefinitions", "/v2/FieldMappingDefinitions")];
+
+/// Every option the FDW accepts.
+const OPTION_REGISTRY: &[OptionSpec] = &[
+    OptionSpec { scope: OptionScope::Server, name: "base_url", endpoints: &[], kind: OptionKind::Text, default: Some(DEFAULT_BASE_URL), description: "Sustainalytics API base URL" },
+    OptionSpec { scope: OptionScope::Server, name: "client_id", endpoints: &[], kind: OptionKind::Text, default: None, description: "OAuth client id (required)" },
+    OptionSpec { scope: OptionScope::Server, name: "client_secret", endpoints: &[], kind: OptionKind::Text, default: None, description: "OAuth client secret (required)" },
+    OptionSpec { scope: OptionScope::Server, name: "max_redirects", endpoints: &[], kind: OptionKind::Int { min: 0, max: 20 }, default: Some("5"), description: "3xx redirects followed per request; credentials are only forwarded to the same origin" },
+    OptionSpec { scope: OptionScope::Server, name: "report_timezone", endpoints: &[], kind: OptionKind::Text, default: Some("UTC"), description: "UTC or a fixed offset like +02:00 for FDW-generated timestamps in timestamp/text columns and stats" },
+    OptionSpec { scope: OptionScope::Server, name: "verify_responses", endpoints: &[], kind: OptionKind::Bool, default: Some("false"), description: "Check bodies against Content-Length and x-content-sha256 headers, retrying on mismatch" },
+    OptionSpec { scope: OptionScope::Server, name: "available_endpoints", endpoints: &[], kind: OptionKind::Text, default: None, description: "Data endpoints the gateway exposes (comma-separated), or 'probe' to detect them; all when unset" },
+    OptionSpec { scope: OptionScope::Table, name: "endpoint", endpoints: ENDPOINTS, kind: OptionKind::Choice(ENDPOINTS), default: None, description: "Endpoint exposed by the table (required)" },
+    OptionSpec { scope: OptionScope::Table, name: "ProductId", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: None, description: "Product to extract (required)" },
+    OptionSpec { scope: OptionScope::Table, name: "PackageIds", endpoints: DATA_SERVICES, kind: OptionKind::IdList, default: None, description: "Comma-separated package ids" },
+    OptionSpec { scope: OptionScope::Table, name: "FieldClusterIds", endpoints: DATA_SERVICES, kind: OptionKind::IdList, default: None, description: "Comma-separated field cluster ids" },
+    OptionSpec { scope: OptionScope::Table, name: "FieldIds", endpoints: DATA_SERVICES, kind: OptionKind::IdList, default: None, description: "Comma-separated field ids" },
+    OptionSpec { scope: OptionScope::Table, name: "Take", endpoints: DATA_SERVICES, kind: OptionKind::Int { min: 1, max: i64::MAX }, default: Some("10"), description: "Page size, clamped to at most 10" },
+    OptionSpec { scope: OptionScope::Table, name: "Skip", endpoints: DATA_SERVICES, kind: OptionKind::Int { min: 0, max: i64::MAX }, default: Some("0"), description: "Starting offset for skip_take paging" },
+    OptionSpec { scope: OptionScope::Table, name: "paging", endpoints: DATA_SERVICES, kind: OptionKind::Choice(PAGING_MODES), default: Some("skip_take"), description: "Pagination strategy" },
+    OptionSpec { scope: OptionScope::Table, name: "keyset_field", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: Some("entityId"), description: "Row attribute used as the keyset cursor" },
+    OptionSpec { scope: OptionScope::Table, name: "keyset_param", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: Some("After"), description: "Query parameter carrying the keyset cursor" },
+    OptionSpec { scope: OptionScope::Table, name: "token_param", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: Some("ContinuationToken"), description: "Query parameter carrying the continuation token" },
+    OptionSpec { scope: OptionScope::Table, name: "token_field", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: Some("continuationToken"), description: "Response attribute holding the next continuation token" },
+    OptionSpec { scope: OptionScope::Table, name: "validate_options", endpoints: DATA_SERVICES, kind: OptionKind::Bool, default: Some("false"), description: "Check id options against FieldMappingDefinitions before scanning" },
+    OptionSpec { scope: OptionScope::Table, name: "request_mode", endpoints: DATA_SERVICES, kind: OptionKind::Choice(REQUEST_MODES), default: Some("paged"), description: "Paged scan, or one request per pushed-down entityId batch" },
+    OptionSpec { scope: OptionScope::Table, name: "entity_param", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: Some("EntityId"), description: "Query parameter carrying the entity ids in per_entity mode" },
+    OptionSpec { scope: OptionScope::Table, name: "entities_per_request", endpoints: DATA_SERVICES, kind: OptionKind::Int { min: 1, max: 1000 }, default: Some("1"), description: "Entity ids sent per request in per_entity mode" },
+    OptionSpec { scope: OptionScope::Table, name: "throttle_ms", endpoints: DATA_SERVICES, kind: OptionKind::Int { min: 0, max: 60_000 }, default: Some("0"), description: "Pause between consecutive API requests" },
+    OptionSpec { scope: OptionScope::Table, name: "confirm_requests_over", endpoints: DATA_SERVICES, kind: OptionKind::Int { min: 1, max: i64::MAX }, default: None, description: "Fail scans estimated to need more API requests than this" },
+    OptionSpec { scope: OptionScope::Table, name: "allow_large_scan", endpoints: DATA_SERVICES, kind: OptionKind::Bool, default: Some("false"), description: "Bypass confirm_requests_over" },
+    OptionSpec { scope: OptionScope::Table, name: "duplicate_fields", endpoints: DATA_SERVICES, kind: OptionKind::Choice(DUPLICATE_FIELDS), default: Some("first"), description: "Handling of field_<id> columns when a row repeats a fieldId" },
+    OptionSpec { scope: OptionScope::Table, name: "validate_values", endpoints: DATA_SERVICES, kind: OptionKind::Choice(VALUE_CHECKS), default: Some("off"), description: "Check field_<id> values against the catalog's possibleValues" },
+    OptionSpec { scope: OptionScope::Table, name: "transforms", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: None, description: "Per-column transforms, e.g. 'entityName:upper,field_12:round2'" },
+    OptionSpec { scope: OptionScope::Table, name: "lenient", endpoints: DATA_SERVICES, kind: OptionKind::Bool, default: Some("false"), description: "Return NULL for field_<id> values that fail type conversion" },
+    OptionSpec { scope: OptionScope::Table, name: "snapshot_fence_secs", endpoints: DATA_SERVICES, kind: OptionKind::Int { min: 0, max: 86_400 }, default: Some("0"), description: "Pin scans of the same ProductId started within this many seconds to one upstream asOf; 0 disables" },
+    OptionSpec { scope: OptionScope::Table, name: "AsOf_start", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: None, description: "First date (YYYY-MM-DD) of a point-in-time backfill" },
+    OptionSpec { scope: OptionScope::Table, name: "AsOf_end", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: None, description: "Last date (YYYY-MM-DD, inclusive) of a point-in-time backfill" },
+    OptionSpec { scope: OptionScope::Table, name: "AsOf_step", endpoints: DATA_SERVICES, kind: OptionKind::Text, default: Some("1d"), description: "Interval between backfill dates: <n>d, <n>w or <n>m" },
+    OptionSpec { scope: OptionScope::Table, name: "summary_row", endpoints: DATA_SERVICES, kind: OptionKind::Choice(SUMMARY_ROWS), default: Some("false"), description: "Report load totals at scan end: 'true' appends a synthetic row, 'notice' raises a notice" },
+    OptionSpec { scope: OptionScope::Table, name: "future_columns", endpoints: DATA_ENDPOINTS, kind: OptionKind::Text, default: None, description: "Comma-separated columns this FDW build doesn't provide yet; they return NULL with one warning" },
+];
+
+fn option_spec(scope: OptionScope, name: &str) -> FdwResult<&'static OptionSpec> {
+    OPTION_REGISTRY
+        .iter()
+        .find(|o| o.scope == scope && o.name == name)
+        .ok_or_else(|| format!("unsupported {} option: {}", scope.as_str(), name).into())
+}
+
+/// Server options the Wrappers host itself requires on every WASM FDW server;
+/// they are accepted without being registered.
+const HOST_SERVER_OPTIONS: &[&str] = &["fdw_package_url", "fdw_package_name", "fdw_package_version", "fdw_package_checksum"];
+
+fn validate_options<'o>(scope: OptionScope, options: impl IntoIterator<Item = (&'o str, &'o str)>) -> FdwResult<()> {
+    for (k, v) in options {
+        if scope == OptionScope::Server && HOST_SERVER_OPTIONS.contains(&k) {
+            continue;
+        }
+        option_spec(scope, k)?.kind.validate(k, v)?;
+    }
+    Ok(())
+}
+
+/// Host options read through `OPTION_REGISTRY`: values are validated against
+/// their kind and fall back to the registered default.
+struct TypedOptions<'a> {
+    scope: OptionScope,
+    raw: &'a Options,
+}
+
+impl<'a> TypedOptions<'a> {
+    fn new(scope: OptionScope, raw: &'a Options) -> Self {
+        Self { scope, raw }
+    }
+
+    /// Reject table options the endpoint doesn't accept, listing the ones it does.
+    fn check_endpoint(&self, endpoint: &str) -> FdwResult<()> {
+        let accepts = |o: &&OptionSpec| o.scope == self.scope && o.endpoints.contains(&endpoint);
+        for (k, _) in self.raw.iter() {
+            if !OPTION_REGISTRY.iter().filter(accepts).any(|o| o.name == k) {
+                let valid: Vec<&str> = OPTION_REGISTRY.iter().filter(accepts).map(|o| o.name).collect();
+                return Err(format!(
+                    "unsupported table option for {}: {} (valid options: {})",
+                    endpoint,
+                    k,
+                    valid.join(", ")
+                ).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate every option that is set, so a bad value fails the statement
+    /// even when the current mode doesn't read it.
+    fn validate_all(&self) -> FdwResult<()> {
+        validate_options(self.scope, self.raw.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+    }
+
+    fn value(&self, name: &str) -> FdwResult<Option<&'a str>> {
+        let spec = option_spec(self.scope, name)?;
+        let value = self.raw.get(name).or(spec.default);
+        if let Some(v) = value {
+            spec.kind.validate(name, v)?;
+        }
+        Ok(value)
+    }
+
+    fn text(&self, name: &str) -> FdwResult<Option<String>> {
+        Ok(self.value(name)?.map(|v| v.to_string()))
+    }
+
+    fn require(&self, name: &str) -> FdwResult<String> {
+        self.text(name)?
+            .ok_or_else(|| format!("missing required {} option {}", self.scope.as_str(), name).into())
+    }
+
+    fn flag(&self, name: &str) -> FdwResult<bool> {
+        Ok(self.value(name)? == Some("true"))
+    }
+
+    fn int(&self, name: &str) -> FdwResult<Option<i64>> {
+        Ok(self.value(name)?.and_then(|v| v.trim().parse::<i64>().ok()))
+    }
+
+    fn require_int(&self, name: &str) -> FdwResult<i64> {
+        let v = self.require(name)?;
+        v.trim()
+            .parse::<i64>()
+            .map_err(|_| format!("invalid {}: {} (expected an integer)", name, v).into())
+    }
+}
 
 #[derive(Default, Clone)]
 struct SustainalyticsFdw {
     base_url: String,
     client_id: String,
     client_secret: String,
+    /// Redirects followed per request before giving up.
+    max_redirects: i64,
+    /// `report_timezone` as seconds east of UTC.
+    report_offset: i64,
+    verify_responses: bool,
+    /// Data endpoints the tenant's gateway exposes; `None` when not restricted.
+    available_endpoints: Option<Vec<String>>,
     cached_token: Option<String>,
+    /// HTTP requests sent, including token requests, redirects and retries.
+    http_requests: u64,
+    /// Expiry of `cached_token`, seconds since the Unix epoch.
+    token_expires_at: Option<i64>,
+    cached_catalog: Option<Vec<FieldMappingRow>>,
+    catalog_version: CatalogVersion,
     scan: ScanState,
 }
 
+/// Catalog version reported by the API and the refresh backoff.
+#[derive(Debug, Clone, Default)]
+struct CatalogVersion {
+    seen: Option<String>,
+    /// A different version was seen since the catalog was last loaded.
+    pending: bool,
+    /// Bumped on every invalidation; scans compare it to refresh their column plans.
+    generation: u64,
+    backoff_secs: i64,
+    next_refresh_at: i64,
+}
+
+impl CatalogVersion {
+    fn observe(&mut self, version: &str) {
+        match &self.seen {
+            Some(v) if v == version => {}
+            Some(_) => {
+                self.seen = Some(version.to_string());
+                self.pending = true;
+            }
+            None => self.seen = Some(version.to_string()),
+        }
+    }
+
+    /// Whether a pending change should invalidate the catalog now.
+    fn take_due(&mut self, now: i64) -> bool {
+        if !self.pending || now < self.next_refresh_at {
+            return false;
+        }
+        // Back off only while changes keep arriving; a quiet hour resets it.
+        self.backoff_secs = if now - self.next_refresh_at > MAX_CATALOG_BACKOFF_SECS {
+            CATALOG_BACKOFF_SECS
+        } else {
+            (self.backoff_secs * 2).clamp(CATALOG_BACKOFF_SECS, MAX_CATALOG_BACKOFF_SECS)
+        };
+        self.next_refresh_at = now + self.backoff_secs;
+        self.pending = false;
+        self.generation += 1;
+        true
+    }
+}
+
 #[derive(Default, Clone)]
 enum ScanState {
     #[default]
     None,
-    DataServices(DataServicesScan),
+    DataServices(Box<DataServicesScan>),
     FieldMappingDefinitions(FieldMappingDefinitionsScan),
+    Options(OptionsScan),
+    Health(HealthScan),
 }
 
 #[derive(Default, Clone)]
 struct DataServicesScan {
     params: DataServicesParams,
-    skip: i64,
+    paging: Paging,
     page_rows: Vec<JsonValue>,
     page_idx: usize,
-    done: bool,
+    converter: RowConverter,
+    /// Output rows of the last converted API row (several under `duplicate_fields 'long'`).
+    ready: VecDeque<Vec<Option<Cell>>>,
+    rows_emitted: u64,
+    /// API requests made so far; requests after the first wait `throttle_ms`.
+    requests: u64,
+    /// `confirm_requests_over`, unless `allow_large_scan`.
+    request_limit: Option<u64>,
+    throttle_ms: u64,
+    /// `CatalogVersion::generation` the column plans were built against.
+    catalog_generation: u64,
+    /// Window for sharing a snapshot with the product's other tables; 0 disables the fence.
+    snapshot_fence_secs: i64,
+    backfill: Option<Backfill>,
+    /// Reads every entity of the product: no entityId conditions, no Skip and
+    /// no backfill. Only such scans are recorded in `last_sync`.
+    full_scan: bool,
+    summary_row: SummaryRow,
+    /// Scan start and `http_requests` at that point, for the summary.
+    started_at: i64,
+    http_requests_at_start: u64,
+}
+
+/// Load totals reported under `summary_row`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum SummaryRow {
+    #[default]
+    Off,
+    /// Appended as a synthetic row with entityId `_summary`.
+    Row,
+    Notice,
+    /// Already reported.
+    Done,
+}
+
+/// Point-in-time requests of an `AsOf_start`..`AsOf_end` backfill: the full
+/// paged scan is repeated for each date with `AsOf` set.
+#[derive(Default, Clone)]
+struct Backfill {
+    /// Dates still to scan after the current one, as days since the Unix epoch.
+    dates: VecDeque<i64>,
+    /// Pager as built by `begin_scan`, restarted for every date.
+    paging: Paging,
+}
+
+impl Backfill {
+    /// Dates from `start` to `end` inclusive every `step`: `<n>d`, `<n>w` or
+    /// `<n>m` (months, kept on the start's day of month where it exists), with
+    /// `n` at most `MAX_ASOF_DATES` so the date arithmetic stays in range.
+    fn dates(start: i64, end: i64, step: &str) -> FdwResult<VecDeque<i64>> {
+        if end < start {
+            return Err(format!("AsOf_end {} is before AsOf_start {}", format_date(end), format_date(start)).into());
+        }
+        let invalid = || format!("invalid AsOf_step: {} (expected e.g. 1d, 1w or 1m)", step);
+        let step = step.trim();
+        let (n, unit) = match step.char_indices().last() {
+            Some((i, _)) => step.split_at(i),
+            None => return Err(invalid().into()),
+        };
+        let n: i64 = n.parse().map_err(|_| invalid())?;
+        if !(1..=MAX_ASOF_DATES as i64).contains(&n) {
+            return Err(invalid().into());
+        }
+
+        let (y, m, d) = civil_from_days(start);
+        let mut dates = VecDeque::new();
+        for i in 0.. {
+            let date = match unit {
+                "d" => start + i * n,
+                "w" => start + i * n * 7,
+                "m" => {
+                    let months = m - 1 + i * n;
+                    let (ty, tm) = (y + months.div_euclid(12), months.rem_euclid(12) + 1);
+                    let first = days_from_civil(ty, tm, 1);
+                    let month_len = days_from_civil(ty + tm / 12, tm % 12 + 1, 1) - first;
+                    first + d.min(month_len) - 1
+                }
+                _ => return Err(invalid().into()),
+            };
+            if date > end {
+                break;
+            }
+            if dates.len() >= MAX_ASOF_DATES {
+                return Err(format!("AsOf range has more than {} dates; use a larger AsOf_step", MAX_ASOF_DATES).into());
+            }
+            dates.push_back(date);
+        }
+        Ok(dates)
+    }
+}
+
+/// DataServices columns served from the row itself rather than a mapped field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DataColumn {
+    EntityId,
+    EntityName,
+    Fields,
+    FetchedAt,
+    AsOf,
+}
+
+/// Built-in DataServices column names, shared by row conversion and
+/// `future_columns` so the two can't disagree about what this build provides.
+const DATA_SERVICES_COLUMNS: &[(&str, DataColumn)] = &[
+    ("entityId", DataColumn::EntityId),
+    ("entityName", DataColumn::EntityName),
+    ("fields", DataColumn::Fields),
+    ("_fetched_at", DataColumn::FetchedAt),
+    ("as_of", DataColumn::AsOf),
+];
+
+impl DataColumn {
+    fn parse(name: &str) -> Option<Self> {
+        DATA_SERVICES_COLUMNS.iter().find(|(n, _)| *n == name).map(|(_, c)| *c)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ColumnSpec {
+    name: String,
+    type_oid: TypeOid,
+    /// Set for mapped columns (`field_<fieldId>`), which read one entry of `fields`.
+    field_id: Option<i64>,
+    /// Allowed values from the catalog's `possibleValues`, when `validate_values` is on.
+    domain: Option<Vec<String>>,
+    transform: Option<Transform>,
+    /// Listed in `future_columns` and unknown to this build: always NULL.
+    future: bool,
+}
+
+impl ColumnSpec {
+    fn from_context(ctx: &Context) -> Vec<ColumnSpec> {
+        ctx.get_columns()
+            .iter()
+            .map(|c| ColumnSpec {
+                name: c.name().to_string(),
+                type_oid: c.type_oid(),
+                field_id: c.name().strip_prefix("field_").and_then(|id| id.parse().ok()),
+                domain: None,
+                transform: None,
+                future: false,
+            })
+            .collect()
+    }
+
+    fn is_jsonb(&self) -> bool {
+        matches!(self.type_oid, TypeOid::Jsonb)
+    }
+
+    /// Apply the `transforms` table option (`'col:upper,other_col:round2'`).
+    /// Postgres column options aren't visible to WASM FDWs, hence the table-level map.
+    fn apply_transforms(columns: &mut [ColumnSpec], spec: &str) -> FdwResult<()> {
+        for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let (name, t) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("invalid transforms entry: {} (expected column:transform)", entry))?;
+            let transform = Transform::parse(t.trim())?;
+            let col = columns
+                .iter_mut()
+                .find(|c| c.name == name.trim())
+                .ok_or_else(|| format!("transforms: no column named {}", name.trim()))?;
+            if !transform.accepts(col.type_oid) {
+                return Err(format!("transforms: {} does not apply to column {} of type {:?}", t.trim(), col.name, col.type_oid).into());
+            }
+            col.transform = Some(transform);
+        }
+        Ok(())
+    }
+
+    /// Mark the columns listed in `future_columns` that this build has no
+    /// source for, returning their names. Listed columns the build does know
+    /// are served normally, so the option can stay in place after an upgrade.
+    fn apply_future(columns: &mut [ColumnSpec], spec: &str) -> Vec<String> {
+        let listed = name_list(spec);
+        let mut marked = Vec::new();
+        for col in columns.iter_mut() {
+            let known = DataColumn::parse(&col.name).is_some() || col.field_id.is_some();
+            if !known && listed.contains(&col.name.as_str()) {
+                col.future = true;
+                marked.push(col.name.clone());
+            }
+        }
+        marked
+    }
+}
+
+/// Warn, before any request, about `WHERE entityId` ids that can't match:
+/// ISIN-shaped entries (two leading letters) of the wrong length, other
+/// entries that aren't numeric entityIds, and lists mixing both forms. The
+/// API answers those with empty results rather than an error.
+fn warn_invalid_ids(ids: &[String]) {
+    let is_isin_shaped = |id: &str| id.len() >= 2 && id.as_bytes()[..2].iter().all(|b| b.is_ascii_alphabetic());
+    let mut invalid: Vec<String> = Vec::new();
+    for id in ids {
+        if id.trim() != id || !id.is_ascii() {
+            invalid.push(format!("{:?} (whitespace or non-ASCII characters)", id));
+        } else if is_isin_shaped(id) {
+            if id.len() != 12 || !id.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                invalid.push(format!("{} (ISIN with {} characters, expected 12 letters/digits)", id, id.len()));
+            }
+        } else if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+            invalid.push(format!("{:?} (not a numeric entityId)", id));
+        }
+    }
+    let isins = ids.iter().filter(|id| is_isin_shaped(id)).count();
+    if isins > 0 && isins < ids.len() {
+        invalid.push(format!("{} ISIN-shaped and {} other ids mixed in one list", isins, ids.len() - isins));
+    }
+    if !invalid.is_empty() {
+        utils::report_warning(&format!(
+            "{}: {} entityId condition entries will likely match nothing: {}",
+            FDW_NAME,
+            invalid.len(),
+            invalid.join("; ")
+        ));
+    }
+}
+
+/// Comma-separated names, trimmed, empty entries dropped.
+fn name_list(spec: &str) -> Vec<&str> {
+    spec.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()).collect()
+}
+
+/// The single warning for a scan selecting `future_columns`.
+fn warn_future_columns(endpoint: &str, names: &[String]) {
+    if names.is_empty() {
+        return;
+    }
+    utils::report_warning(&format!(
+        "{}: {} columns {} are not provided by this FDW build and return NULL (future_columns)",
+        FDW_NAME,
+        endpoint,
+        names.join(", ")
+    ));
+}
+
+/// Cosmetic normalization applied while converting a cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transform {
+    Upper,
+    Lower,
+    /// Round to two decimal places.
+    Round2,
+    /// Fraction to percentage (x 100).
+    Percent,
+}
+
+impl Transform {
+    fn parse(s: &str) -> FdwResult<Self> {
+        match s {
+            "upper" => Ok(Transform::Upper),
+            "lower" => Ok(Transform::Lower),
+            "round2" => Ok(Transform::Round2),
+            "percent" => Ok(Transform::Percent),
+            other => Err(format!("unknown transform: {} (expected upper, lower, round2 or percent)", other).into()),
+        }
+    }
+
+    fn accepts(self, type_oid: TypeOid) -> bool {
+        match self {
+            Transform::Upper | Transform::Lower => matches!(type_oid, TypeOid::String),
+            Transform::Round2 | Transform::Percent => matches!(type_oid, TypeOid::F32 | TypeOid::F64 | TypeOid::Numeric),
+        }
+    }
+
+    fn apply(self, cell: Cell) -> Cell {
+        let num = |f: f64| match self {
+            Transform::Round2 => (f * 100.0).round() / 100.0,
+            Transform::Percent => f * 100.0,
+            Transform::Upper | Transform::Lower => f,
+        };
+        match (self, cell) {
+            (Transform::Upper, Cell::String(s)) => Cell::String(s.to_uppercase()),
+            (Transform::Lower, Cell::String(s)) => Cell::String(s.to_lowercase()),
+            (_, Cell::F64(f)) => Cell::F64(num(f)),
+            (_, Cell::F32(f)) => Cell::F32(num(f as f64) as f32),
+            (_, Cell::Numeric(f)) => Cell::Numeric(num(f)),
+            (_, other) => other,
+        }
+    }
+}
+
+/// Look up an attribute regardless of the gateway's casing convention: the
+/// name as given, then with its first letter case-swapped (so `entityId` also
+/// matches `EntityId` and vice versa).
+fn attr<'v>(v: &'v JsonValue, name: &str) -> Option<&'v JsonValue> {
+    if let Some(found) = v.get(name) {
+        return Some(found);
+    }
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    let swapped: String = if first.is_uppercase() {
+        first.to_lowercase().chain(chars).collect()
+    } else {
+        first.to_uppercase().chain(chars).collect()
+    };
+    v.get(swapped.as_str())
+}
+
+/// What to do when a row's `fields` array holds the same fieldId more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum DuplicateFields {
+    #[default]
+    First,
+    Last,
+    Error,
+    /// Emit one row per occurrence; row i holds each column's i-th occurrence or NULL.
+    Long,
+}
+
+impl DuplicateFields {
+    fn parse(s: &str) -> Self {
+        match s {
+            "last" => DuplicateFields::Last,
+            "error" => DuplicateFields::Error,
+            "long" => DuplicateFields::Long,
+            _ => DuplicateFields::First,
+        }
+    }
+}
+
+/// Index a row's `fields` array as fieldId -> values (in array order), so
+/// wide tables look each mapped column up instead of rescanning the array.
+fn field_index(src: &JsonValue) -> HashMap<i64, Vec<&JsonValue>> {
+    let mut index: HashMap<i64, Vec<&JsonValue>> = HashMap::new();
+    let Some(entries) = attr(src, "fields").and_then(|v| v.as_array()) else { return index; };
+    for e in entries {
+        let Some(id) = attr(e, "fieldId").and_then(|v| v.as_i64()) else { continue; };
+        index.entry(id).or_default().push(attr(e, "value").unwrap_or(&JsonValue::Null));
+    }
+    index
+}
+
+/// Convert a JSON value to a cell of the column's declared type. Numbers and
+/// booleans delivered as strings are accepted.
+fn json_to_cell(v: &JsonValue, col: &ColumnSpec) -> FdwResult<Option<Cell>> {
+    if v.is_null() {
+        return Ok(None);
+    }
+
+    let text = json_value_text(v);
+    let mismatch = || format!("column {}: cannot convert JSON {} {} to {:?}", col.name, json_type_name(v), text, col.type_oid);
+    let int = || v.as_i64().or_else(|| text.trim().parse::<i64>().ok()).ok_or_else(mismatch);
+    let float = || v.as_f64().or_else(|| text.trim().parse::<f64>().ok()).ok_or_else(mismatch);
+
+    let cell = match col.type_oid {
+        TypeOid::Bool => Cell::Bool(v.as_bool().or_else(|| text.trim().parse::<bool>().ok()).ok_or_else(mismatch)?),
+        TypeOid::I8 => Cell::I8(i8::try_from(int()?).map_err(|_| mismatch())?),
+        TypeOid::I16 => Cell::I16(i16::try_from(int()?).map_err(|_| mismatch())?),
+        TypeOid::I32 => Cell::I32(i32::try_from(int()?).map_err(|_| mismatch())?),
+        TypeOid::I64 => Cell::I64(int()?),
+        TypeOid::F32 => Cell::F32(float()? as f32),
+        TypeOid::F64 => Cell::F64(float()?),
+        TypeOid::Numeric => Cell::Numeric(float()?),
+        TypeOid::String => Cell::String(text),
+        TypeOid::Date => Cell::Date(time::parse_from_str(&text, "%Y-%m-%d").map_err(|_| mismatch())? / 1_000_000),
+        TypeOid::Timestamp => Cell::Timestamp(time::parse_from_rfc3339(&text).map_err(|_| mismatch())?),
+        TypeOid::Timestamptz => Cell::Timestamptz(time::parse_from_rfc3339(&text).map_err(|_| mismatch())?),
+        TypeOid::Jsonb => Cell::Jsonb(v.to_string()),
+    };
+    Ok(Some(cell))
+}
+
+/// How mapped values outside the catalog's `possibleValues` are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum ValueCheck {
+    #[default]
+    Off,
+    Warn,
+    Null,
+}
+
+impl ValueCheck {
+    fn parse(s: &str) -> Self {
+        match s {
+            "warn" => ValueCheck::Warn,
+            "null" => ValueCheck::Null,
+            _ => ValueCheck::Off,
+        }
+    }
+}
+
+/// Split a catalog `possibleValues` string ("Low; Medium; High", "0,1") into
+/// values. Only clear enumerations count: at least two short tokens, none of
+/// them a range ("0-100", "1 to 5") and the text no sentence. Descriptive
+/// strings yield `None` (no domain) instead of a domain every value misses.
+fn parse_possible_values(s: &str) -> Option<Vec<String>> {
+    const MAX_TOKEN_CHARS: usize = 40;
+    const MAX_TOKEN_WORDS: usize = 4;
+
+    if s.trim_end().ends_with('.') {
+        return None;
+    }
+    let values: Vec<String> = s
+        .split([',', ';', '|'])
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .collect();
+    let enumeration = values.len() >= 2
+        && values.iter().all(|v| {
+            v.chars().count() <= MAX_TOKEN_CHARS && v.split_whitespace().count() <= MAX_TOKEN_WORDS && !is_range(v)
+        });
+    if enumeration { Some(values) } else { None }
+}
+
+/// Whether a token reads as a numeric range: "0-100", "1 to 5", "0..1", "-5 – 5".
+fn is_range(v: &str) -> bool {
+    let body = v.strip_prefix('-').unwrap_or(v);
+    ["..", "–", " to ", "-"].iter().any(|sep| {
+        body.split_once(sep).is_some_and(|(lo, hi)| {
+            lo.trim_end().ends_with(|c: char| c.is_ascii_digit())
+                && hi.trim_start().trim_start_matches('-').starts_with(|c: char| c.is_ascii_digit())
+        })
+    })
+}
+
+/// Turns API rows into output rows for the declared columns. Owns the scan's
+/// column plan and the data-quality counters reported when the scan ends.
+#[derive(Default, Clone)]
+struct RowConverter {
+    columns: Vec<ColumnSpec>,
+    duplicate_fields: DuplicateFields,
+    validate_values: ValueCheck,
+    /// Per column, set once a non-null value has been checked against the
+    /// declared type. Empty until the first row arrives.
+    schema_checked: Vec<bool>,
+    /// Out-of-domain values per column: count and the first value seen.
+    out_of_domain: BTreeMap<String, (u64, String)>,
+    /// Return NULL for values that fail type conversion instead of failing the scan.
+    lenient: bool,
+    /// Conversion failures per column under `lenient`.
+    conversion_failures: BTreeMap<String, u64>,
+    /// When the current page was fetched, for `_fetched_at`.
+    fetched_at: i64,
+    report_offset: i64,
+    /// Date of the backfill step being scanned, for `as_of`.
+    as_of: Option<i64>,
+}
+
+impl RowConverter {
+    /// Compare each column's first non-null value against its declared type: a
+    /// jsonb column fed a scalar (or a scalar column fed an array/object) would
+    /// otherwise only fail as a Postgres cast error somewhere deep into the load.
+    /// `field_<id>` columns are checked against their values in `index`.
+    fn check_schema(&mut self, src: &JsonValue, index: &HashMap<i64, Vec<&JsonValue>>) -> FdwResult<()> {
+        self.schema_checked.resize(self.columns.len(), false);
+        for (col, checked) in self.columns.iter().zip(self.schema_checked.iter_mut()) {
+            if *checked {
+                continue;
+            }
+            let value = match col.field_id {
+                Some(id) => index.get(&id).and_then(|occ| occ.iter().copied().find(|v| !v.is_null())),
+                None => attr(src, &col.name).filter(|v| !v.is_null()),
+            };
+            let Some(v) = value else { continue; };
+            *checked = true;
+            let structured = v.is_array() || v.is_object();
+            if col.is_jsonb() && !structured {
+                return Err(format!(
+                    "column {} is declared jsonb but DataServices returns a JSON {} for it; declare it as a scalar type",
+                    col.name,
+                    json_type_name(v)
+                ).into());
+            }
+            if !col.is_jsonb() && structured {
+                return Err(format!(
+                    "column {} is declared {:?} but DataServices returns a JSON {} for it; declare it jsonb",
+                    col.name,
+                    col.type_oid,
+                    json_type_name(v)
+                ).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert one API row into its output row(s): one row, or one per
+    /// occurrence of a duplicated field under `duplicate_fields 'long'`.
+    fn convert(&mut self, src: &JsonValue) -> FdwResult<Vec<Vec<Option<Cell>>>> {
+        let index = if self.columns.iter().any(|c| c.field_id.is_some()) { field_index(src) } else { HashMap::new() };
+        if self.schema_checked.len() < self.columns.len() || self.schema_checked.contains(&false) {
+            self.check_schema(src, &index)?;
+        }
+
+        let columns = &self.columns;
+        let dup = self.duplicate_fields;
+        let mut rejected: Vec<(String, String)> = Vec::new();
+        let mut failed: Vec<String> = Vec::new();
+        let occurrences: Vec<&[&JsonValue]> = columns
+            .iter()
+            .map(|c| c.field_id.and_then(|id| index.get(&id)).map(|v| v.as_slice()).unwrap_or_default())
+            .collect();
+
+        if dup == DuplicateFields::Error {
+            if let Some((col, occ)) = columns.iter().zip(&occurrences).find(|(_, occ)| occ.len() > 1) {
+                return Err(format!(
+                    "entity {} has {} entries for fieldId {} (column {}); set duplicate_fields to first, last or long",
+                    attr(src, "entityId").map(|v| v.to_string()).unwrap_or_default(),
+                    occ.len(),
+                    col.field_id.unwrap_or_default(),
+                    col.name
+                ).into());
+            }
+        }
+
+        let copies = match dup {
+            DuplicateFields::Long => occurrences.iter().map(|o| o.len()).max().unwrap_or(1).max(1),
+            _ => 1,
+        };
+
+        let mut rows = Vec::with_capacity(copies);
+        for copy in 0..copies {
+            let mut cells = Vec::with_capacity(columns.len());
+            for (col, occ) in columns.iter().zip(&occurrences) {
+                let cell = match DataColumn::parse(&col.name) {
+                    Some(DataColumn::EntityId) => attr(src, "entityId").map(|v| Cell::String(v.to_string().trim_matches('\"').to_string())),
+                    Some(DataColumn::EntityName) => attr(src, "entityName").and_then(|v| v.as_str().map(|s| Cell::String(s.to_string()))),
+                    Some(DataColumn::Fields) => attr(src, "fields").map(|v| Cell::Jsonb(v.to_string())),
+                    Some(DataColumn::FetchedAt) => Some(timestamp_cell(self.fetched_at, &col.name, col.type_oid, self.report_offset)?),
+                    Some(DataColumn::AsOf) => self.as_of.map(|d| date_cell(d, &col.name, col.type_oid)).transpose()?,
+                    None if col.field_id.is_some() => {
+                        let value = match dup {
+                            DuplicateFields::Last => occ.last(),
+                            DuplicateFields::Long => occ.get(copy),
+                            DuplicateFields::First | DuplicateFields::Error => occ.first(),
+                        };
+                        match value {
+                            Some(v) if Self::out_of_domain(col, v) => {
+                                rejected.push((col.name.clone(), json_value_text(v)));
+                                match self.validate_values {
+                                    ValueCheck::Null => None,
+                                    _ => self.to_cell(v, col, &mut failed)?,
+                                }
+                            }
+                            Some(v) => self.to_cell(v, col, &mut failed)?,
+                            None => None,
+                        }
+                    }
+                    None if col.future => None,
+                    None => return Err(format!("unsupported column for DataServices: {}", col.name).into()),
+                };
+                cells.push(match col.transform {
+                    Some(t) => cell.map(|c| t.apply(c)),
+                    None => cell,
+                });
+            }
+            rows.push(cells);
+        }
+
+        for (column, value) in rejected {
+            let entry = self.out_of_domain.entry(column).or_insert((0, value));
+            entry.0 += 1;
+        }
+        for column in failed {
+            *self.conversion_failures.entry(column).or_insert(0) += 1;
+        }
+        Ok(rows)
+    }
+
+    /// Convert a mapped value; under `lenient` a failure becomes NULL and is
+    /// recorded in `failed`, so the row keeps the declared shape.
+    fn to_cell(&self, v: &JsonValue, col: &ColumnSpec, failed: &mut Vec<String>) -> FdwResult<Option<Cell>> {
+        match json_to_cell(v, col) {
+            Err(_) if self.lenient => {
+                failed.push(col.name.clone());
+                Ok(None)
+            }
+            other => other,
+        }
+    }
+
+    /// Whether a value misses the column's domain. Numbers compare by value,
+    /// so JSON `1.0` matches a `possibleValues` token `1`.
+    fn out_of_domain(col: &ColumnSpec, v: &JsonValue) -> bool {
+        let Some(domain) = &col.domain else { return false; };
+        if v.is_null() {
+            return false;
+        }
+        let text = json_value_text(v);
+        let number = text.trim().parse::<f64>().ok();
+        !domain.iter().any(|token| {
+            *token == text || number.is_some_and(|n| token.parse::<f64>().is_ok_and(|t| t == n))
+        })
+    }
+
+    /// Raise the aggregated data-quality warnings for the scan.
+    fn report(&self) {
+        for (column, (count, first)) in &self.out_of_domain {
+            let action = if self.validate_values == ValueCheck::Null { "replaced with NULL" } else { "kept" };
+            utils::report_warning(&format!(
+                "{}: column {}: {} value(s) outside the catalog's possibleValues ({}), first: {}",
+                FDW_NAME, column, count, action, first
+            ));
+        }
+
+        if !self.conversion_failures.is_empty() {
+            let counts: Vec<String> = self
+                .conversion_failures
+                .iter()
+                .map(|(column, n)| format!("{} ({})", column, n))
+                .collect();
+            utils::report_warning(&format!(
+                "{}: lenient: values that could not be converted were returned as NULL: {}",
+                FDW_NAME,
+                counts.join(", ")
+            ));
+        }
+    }
+}
+
+/// Parse the `report_timezone` server option: `UTC`/`Z` or a fixed offset
+/// `+HH:MM`/`-HH:MM`, returned in seconds east of UTC. Named zones would need a
+/// tz database the WASM sandbox doesn't have.
+fn parse_utc_offset(s: &str) -> FdwResult<i64> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("utc") || s == "Z" {
+        return Ok(0);
+    }
+    let invalid = || format!("invalid report_timezone: {} (expected UTC or an offset like +02:00)", s);
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => return Err(invalid().into()),
+    };
+    let (h, m) = rest.split_once(':').unwrap_or((rest, "0"));
+    let h: i64 = h.parse().map_err(|_| invalid())?;
+    let m: i64 = m.parse().map_err(|_| invalid())?;
+    if !(0..=14).contains(&h) || !(0..60).contains(&m) {
+        return Err(invalid().into());
+    }
+    Ok(sign * (h * 3600 + m * 60))
+}
+
+/// Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Days since 1970-01-01 of a civil date; the inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Parse a `YYYY-MM-DD` date (years 1 to 9999) into days since the Unix epoch.
+fn parse_date(name: &str, s: &str) -> FdwResult<i64> {
+    let invalid = || format!("invalid {}: {} (expected YYYY-MM-DD)", name, s);
+    let mut parts = s.trim().splitn(3, '-').map(|p| p.parse::<i64>());
+    let (Some(Ok(y)), Some(Ok(m)), Some(Ok(d))) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid().into());
+    };
+    if !(1..=9999).contains(&y) || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return Err(invalid().into());
+    }
+    let days = days_from_civil(y, m, d);
+    if civil_from_days(days) != (y, m, d) {
+        return Err(invalid().into());
+    }
+    Ok(days)
+}
+
+fn format_date(days: i64) -> String {
+    let (y, m, d) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+/// RFC 3339 text for an epoch timestamp, as wall-clock time at `offset` seconds east of UTC.
+fn format_rfc3339(epoch_secs: i64, offset: i64) -> String {
+    let local = epoch_secs + offset;
+    let (days, secs) = (local.div_euclid(86_400), local.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+
+    let zone = if offset == 0 {
+        "Z".to_string()
+    } else {
+        format!("{}{:02}:{:02}", if offset < 0 { '-' } else { '+' }, offset.abs() / 3600, offset.abs() % 3600 / 60)
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        year, month, day, secs / 3600, secs % 3600 / 60, secs % 60, zone
+    )
+}
+
+/// Cell for an FDW-generated timestamp. `timestamptz` columns carry the UTC
+/// instant; `timestamp` and `text` columns get wall-clock time in the
+/// `report_timezone`.
+fn timestamp_cell(epoch_secs: i64, col: &str, type_oid: TypeOid, offset: i64) -> FdwResult<Cell> {
+    match type_oid {
+        TypeOid::Timestamptz => Ok(Cell::Timestamptz(epoch_secs * 1_000_000)),
+        TypeOid::Timestamp => Ok(Cell::Timestamp((epoch_secs + offset) * 1_000_000)),
+        TypeOid::String => Ok(Cell::String(format_rfc3339(epoch_secs, offset))),
+        other => Err(format!("column {} must be timestamptz, timestamp or text, not {:?}", col, other).into()),
+    }
+}
+
+fn metadata_key(key: &str) -> Option<JsonValue> {
+    stats::get_metadata(FDW_NAME)
+        .and_then(|m| serde_json::from_str::<JsonValue>(&m).ok())
+        .and_then(|v| v.get(key).cloned())
+}
+
+/// Replace one key of the FDW stats metadata, keeping whatever else is stored.
+fn set_metadata_key(key: &str, value: JsonValue) {
+    let mut metadata = stats::get_metadata(FDW_NAME)
+        .and_then(|m| serde_json::from_str::<JsonValue>(&m).ok())
+        .and_then(|v| v.as_object().cloned())
+        .unwrap_or_default();
+    metadata.insert(key.to_string(), value);
+    stats::set_metadata(FDW_NAME, Some(&JsonValue::Object(metadata).to_string()));
+}
+
+const JSON_TOO_DEEP: &str = "nests JSON deeper than";
+
+/// Parse a response body, naming the nesting limit when that is what failed.
+fn parse_json(body: &str) -> Result<JsonValue, String> {
+    serde_json::from_str(body).map_err(|e| match e.to_string() {
+        e if e.contains("recursion limit") => format!("response {} {} levels", JSON_TOO_DEEP, MAX_JSON_DEPTH),
+        e => format!("invalid json: {e}"),
+    })
+}
+
+/// Copy of `v` with containers nested more than `depth` levels down replaced
+/// by null, and whether anything was cut. Recursion is bounded by `depth`, so
+/// pathological inputs can't exhaust the guest stack.
+fn truncate_depth(v: &JsonValue, depth: usize) -> (JsonValue, bool) {
+    match v {
+        JsonValue::Array(_) | JsonValue::Object(_) if depth == 0 => (JsonValue::Null, true),
+        JsonValue::Array(items) => {
+            let mut cut = false;
+            let items = items
+                .iter()
+                .map(|i| {
+                    let (i, c) = truncate_depth(i, depth - 1);
+                    cut |= c;
+                    i
+                })
+                .collect();
+            (JsonValue::Array(items), cut)
+        }
+        JsonValue::Object(map) => {
+            let mut cut = false;
+            let mut out = serde_json::Map::new();
+            for (k, i) in map {
+                let (i, c) = truncate_depth(i, depth - 1);
+                cut |= c;
+                out.insert(k.clone(), i);
+            }
+            (JsonValue::Object(out), cut)
+        }
+        other => (other.clone(), false),
+    }
+}
+
+/// Cell for a date given as days since the Unix epoch.
+fn date_cell(days: i64, col: &str, type_oid: TypeOid) -> FdwResult<Cell> {
+    match type_oid {
+        TypeOid::Date => Ok(Cell::Date(days * 86_400)),
+        TypeOid::Timestamp => Ok(Cell::Timestamp(days * 86_400 * 1_000_000)),
+        TypeOid::Timestamptz => Ok(Cell::Timestamptz(days * 86_400 * 1_000_000)),
+        TypeOid::String => Ok(Cell::String(format_date(days))),
+        other => Err(format!("column {} must be date, timestamp or text, not {:?}", col, other).into()),
+    }
+}
+
+/// Text form of a value for comparisons: strings unquoted, everything else as JSON.
+fn json_value_text(v: &JsonValue) -> String {
+    match v {
+        JsonValue::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn json_type_name(v: &JsonValue) -> &'static str {
+    match v {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
 }
 
 #[derive(Default, Clone)]
@@ -55,16 +1172,315 @@ struct DataServicesParams {
     package_ids: Option<String>,
     field_cluster_ids: Option<String>,
     field_ids: Option<String>,
+    /// Upstream snapshot pinned by `snapshot_fence_secs`.
+    as_of: Option<String>,
+}
+
+/// Pagination strategy for a paged endpoint.
+///
+/// A pager owns the cursor for one scan: it contributes the query parameters
+/// selecting the next page and inspects every received page to decide whether
+/// another request is needed. Endpoints only build URLs and parse rows.
+trait Pager {
+    /// Query parameters selecting the next page.
+    fn query_params(&self) -> Vec<(String, String)>;
+
+    /// Move the cursor past a received page.
+    fn advance(&mut self, response: &JsonValue, rows: &[JsonValue]) -> FdwResult<()>;
+
+    /// True once the last page has been received.
+    fn is_done(&self) -> bool;
+
+    /// Requests still needed to finish the scan, if the pager can tell.
+    fn remaining_requests(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Offset paging via `Skip`/`Take`; a short page, or reaching the
+/// `totalCount` of an envelope response, ends the scan. A gateway that ignores
+/// `Skip` is caught by a page repeating its predecessor, or (without a
+/// `totalCount`) by `Skip` passing `MAX_TOTAL_ROWS`.
+#[derive(Default, Clone)]
+struct SkipTakePager {
+    skip: i64,
+    take: i64,
+    total: Option<i64>,
+    /// First row of the previous page, serialized.
+    last_first_row: Option<String>,
+    done: bool,
+}
+
+impl Pager for SkipTakePager {
+    fn query_params(&self) -> Vec<(String, String)> {
+        vec![
+            ("Skip".to_owned(), self.skip.to_string()),
+            ("Take".to_owned(), self.take.to_string()),
+        ]
+    }
+
+    fn advance(&mut self, response: &JsonValue, rows: &[JsonValue]) -> FdwResult<()> {
+        if let Some(v) = attr(response, "totalCount") {
+            let total = v
+                .as_i64()
+                .filter(|t| (0..=MAX_TOTAL_ROWS).contains(t))
+                .ok_or_else(|| format!("implausible totalCount in response: {}", v))?;
+            self.total = Some(total);
+        }
+
+        let first_row = rows.first().map(|r| r.to_string());
+        if first_row.is_some() && first_row == self.last_first_row {
+            return Err(format!("skip_take paging: page at Skip {} repeats the previous page; the API seems to ignore Skip", self.skip).into());
+        }
+        self.last_first_row = first_row;
+
+        if (rows.len() as i64) < self.take {
+            self.done = true;
+            return Ok(());
+        }
+
+        self.skip = self.skip.checked_add(self.take).ok_or("Skip overflowed while paging")?;
+        if self.total.is_some_and(|total| self.skip >= total) {
+            self.done = true;
+        } else if self.total.is_none() && self.skip > MAX_TOTAL_ROWS {
+            return Err(format!("skip_take paging: Skip passed {} rows without a totalCount; stopping", MAX_TOTAL_ROWS).into());
+        }
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn remaining_requests(&self) -> Option<u64> {
+        if self.done {
+            return Some(0);
+        }
+        let rows = self.total?.saturating_sub(self.skip).max(0);
+        Some(((rows + self.take - 1) / self.take).max(1) as u64)
+    }
+}
+
+/// Keyset paging: the key of the last row received is sent as `param` on the
+/// next request, so pages stay stable while the upstream data changes.
+#[derive(Default, Clone)]
+struct KeysetPager {
     take: i64,
+    key_field: String,
+    param: String,
+    last_key: Option<String>,
+    done: bool,
+}
+
+impl Pager for KeysetPager {
+    fn query_params(&self) -> Vec<(String, String)> {
+        let mut params = vec![("Take".to_owned(), self.take.to_string())];
+        if let Some(k) = &self.last_key {
+            params.push((self.param.clone(), k.clone()));
+        }
+        params
+    }
+
+    fn advance(&mut self, _response: &JsonValue, rows: &[JsonValue]) -> FdwResult<()> {
+        if (rows.len() as i64) < self.take {
+            self.done = true;
+            return Ok(());
+        }
+
+        let last = rows.last().and_then(|r| attr(r, &self.key_field));
+        let key = match last {
+            Some(JsonValue::String(s)) => s.clone(),
+            Some(v) if !v.is_null() => v.to_string(),
+            _ => return Err(format!("keyset paging: last row has no {} value", self.key_field).into()),
+        };
+        if self.last_key.as_deref() == Some(key.as_str()) {
+            return Err(format!("keyset paging: cursor did not advance past {}", key).into());
+        }
+        self.last_key = Some(key);
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+/// Continuation-token paging: the response carries an opaque token (body
+/// attribute `field`) that is sent back as `param`; the scan ends when the
+/// response no longer carries one.
+#[derive(Default, Clone)]
+struct TokenPager {
+    take: i64,
+    param: String,
+    field: String,
+    token: Option<String>,
+    done: bool,
+}
+
+impl Pager for TokenPager {
+    fn query_params(&self) -> Vec<(String, String)> {
+        let mut params = vec![("Take".to_owned(), self.take.to_string())];
+        if let Some(t) = &self.token {
+            params.push((self.param.clone(), t.clone()));
+        }
+        params
+    }
+
+    fn advance(&mut self, response: &JsonValue, _rows: &[JsonValue]) -> FdwResult<()> {
+        let next = attr(response, &self.field)
+            .and_then(|v| v.as_str())
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_string());
+        if next.is_some() && next == self.token {
+            return Err("token paging: API returned the same continuation token twice".into());
+        }
+        self.done = next.is_none();
+        self.token = next;
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+/// One request per batch of entity ids, for endpoints that accept only a
+/// single entity (or a few) per request. The ids come from pushed-down
+/// `entityId` conditions.
+#[derive(Default, Clone)]
+struct EntityPager {
+    param: String,
+    batches: Vec<String>,
+    next: usize,
+}
+
+impl Pager for EntityPager {
+    fn query_params(&self) -> Vec<(String, String)> {
+        match self.batches.get(self.next) {
+            Some(batch) => vec![(self.param.clone(), batch.clone())],
+            None => vec![],
+        }
+    }
+
+    fn advance(&mut self, _response: &JsonValue, _rows: &[JsonValue]) -> FdwResult<()> {
+        self.next += 1;
+
+        // Report roughly every 10% so long lists show progress without flooding.
+        let total = self.batches.len();
+        let step = (total / 10).max(1);
+        if self.next.is_multiple_of(step) || self.next == total {
+            utils::report_info(&format!("DataServices per_entity: {}/{} requests done", self.next, total));
+        }
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.next >= self.batches.len()
+    }
+
+    fn remaining_requests(&self) -> Option<u64> {
+        Some(self.batches.len().saturating_sub(self.next) as u64)
+    }
+}
+
+/// The endpoint returns everything in one response.
+#[derive(Default, Clone)]
+struct SingleShotPager {
+    done: bool,
+}
+
+impl Pager for SingleShotPager {
+    fn query_params(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+
+    fn advance(&mut self, _response: &JsonValue, _rows: &[JsonValue]) -> FdwResult<()> {
+        self.done = true;
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn remaining_requests(&self) -> Option<u64> {
+        Some(if self.done { 0 } else { 1 })
+    }
+}
+
+/// The pager selected for a scan, chosen by endpoint default or the `paging` table option.
+#[derive(Clone)]
+enum Paging {
+    SkipTake(SkipTakePager),
+    Keyset(KeysetPager),
+    Token(TokenPager),
+    Entity(EntityPager),
+    SingleShot(SingleShotPager),
+}
+
+impl Default for Paging {
+    fn default() -> Self {
+        Paging::SingleShot(SingleShotPager::default())
+    }
+}
+
+impl Paging {
+    fn pager(&self) -> &dyn Pager {
+        match self {
+            Paging::SkipTake(p) => p,
+            Paging::Keyset(p) => p,
+            Paging::Token(p) => p,
+            Paging::Entity(p) => p,
+            Paging::SingleShot(p) => p,
+        }
+    }
+
+    fn pager_mut(&mut self) -> &mut dyn Pager {
+        match self {
+            Paging::SkipTake(p) => p,
+            Paging::Keyset(p) => p,
+            Paging::Token(p) => p,
+            Paging::Entity(p) => p,
+            Paging::SingleShot(p) => p,
+        }
+    }
 }
 
 #[derive(Default, Clone)]
 struct FieldMappingDefinitionsScan {
     rows: Vec<FieldMappingRow>,
     idx: usize,
+    /// Selected `future_columns` this build doesn't provide: always NULL.
+    future: Vec<String>,
+    /// When the definitions were fetched, for `_fetched_at`.
+    fetched_at: i64,
+    report_offset: i64,
+}
+
+#[derive(Default, Clone)]
+struct OptionsScan {
+    idx: usize,
+    /// Data endpoints missing from `available_endpoints`.
+    unavailable: Vec<&'static str>,
+}
+
+#[derive(Default, Clone)]
+struct HealthScan {
+    row: Option<HealthRow>,
+    report_offset: i64,
 }
 
 #[derive(Debug, Clone)]
+struct HealthRow {
+    ok: bool,
+    checked_at: i64,
+    /// Coarse: difference of two whole-second clock readings.
+    latency_secs: i64,
+    token_expires_at: Option<i64>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
 struct FieldMappingRow {
     product_id: String,
     product_name: Option<String>,
@@ -82,6 +1498,75 @@ struct FieldMappingRow {
     parentage: Option<JsonValue>,
 }
 
+impl FieldMappingRow {
+    /// Cell for the named column, or `None` for a column this build doesn't provide.
+    fn cell(&self, name: &str, type_oid: TypeOid, fetched_at: i64, report_offset: i64) -> Option<FdwResult<Option<Cell>>> {
+        let cell = match name {
+            "product_id" => Some(Cell::String(self.product_id.clone())),
+            "product_name" => self.product_name.clone().map(Cell::String),
+            "package_id" => self.package_id.map(Cell::I64),
+            "package_name" => self.package_name.clone().map(Cell::String),
+            "field_cluster_id" => self.field_cluster_id.map(Cell::I64),
+            "field_cluster_name" => self.field_cluster_name.clone().map(Cell::String),
+            "field_id" => self.field_id.map(Cell::I64),
+            "field_name" => self.field_name.clone().map(Cell::String),
+            "description" => self.description.clone().map(Cell::String),
+            "field_type" => self.field_type.clone().map(Cell::String),
+            "field_length" => self.field_length.clone().map(Cell::String),
+            "possible_values" => self.possible_values.clone().map(Cell::String),
+            "grouping" => self.grouping.clone().map(Cell::String),
+            "parentage" => self.parentage.clone().map(|v| Cell::Jsonb(v.to_string())),
+            "_row_hash" => Some(Cell::String(self.row_hash())),
+            "_fetched_at" => return Some(timestamp_cell(fetched_at, name, type_oid, report_offset).map(Some)),
+            _ => return None,
+        };
+        Some(Ok(cell))
+    }
+
+    /// Stable hash over every attribute (64-bit FNV-1a, hex), so snapshots of
+    /// the catalog can be diffed cheaply. Fields are separated and NULL is
+    /// distinguished from the empty string, so shifting text between
+    /// attributes changes the hash.
+    fn row_hash(&self) -> String {
+        let ints = |v: Option<i64>| v.map(|n| n.to_string());
+        let attrs: [Option<String>; 14] = [
+            Some(self.product_id.clone()),
+            self.product_name.clone(),
+            ints(self.package_id),
+            self.package_name.clone(),
+            ints(self.field_cluster_id),
+            self.field_cluster_name.clone(),
+            ints(self.field_id),
+            self.field_name.clone(),
+            self.description.clone(),
+            self.field_type.clone(),
+            self.field_length.clone(),
+            self.possible_values.clone(),
+            self.grouping.clone(),
+            self.parentage.as_ref().map(|v| v.to_string()),
+        ];
+
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |bytes: &[u8]| {
+            for b in bytes {
+                hash ^= u64::from(*b);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        for attr in &attrs {
+            match attr {
+                Some(v) => {
+                    feed(b"s");
+                    feed(v.as_bytes());
+                }
+                None => feed(b"n"),
+            }
+            feed(&[0x1f]);
+        }
+        format!("{:016x}", hash)
+    }
+}
+
 #[derive(Deserialize)]
 struct TokenResponse {
     access_token: String,
@@ -90,22 +1575,211 @@ struct TokenResponse {
     scope: Option<String>,
 }
 
+/// Value of a response header, matched case-insensitively. Proxies that inject
+/// a header the gateway already sent produce duplicates; the first non-empty
+/// value wins.
+fn header<'h>(headers: &'h [(String, String)], name: &str) -> Option<&'h str> {
+    headers
+        .iter()
+        .filter(|(k, _)| k.trim().eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+        .find(|v| !v.is_empty())
+}
+
+/// `scheme://host[:port]` of an absolute URL, lowercased.
+fn url_origin(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    format!("{}://{}", scheme, authority).to_ascii_lowercase()
+}
+
+/// Check a body against the `Content-Length` and `x-content-sha256` headers the
+/// gateway sent, if any. The host hands over the body decoded (content
+/// encoding removed, bytes as UTF-8 text), so Content-Length is skipped for
+/// encoded responses and for bodies with U+FFFD replacement characters, whose
+/// length no longer matches the bytes on the wire.
+fn verify_body(resp: &http::Response) -> Result<(), String> {
+    if header(&resp.headers, "content-encoding").is_none() && !resp.body.contains('\u{FFFD}') {
+        if let Some(len) = header(&resp.headers, "content-length").and_then(|v| v.parse::<usize>().ok()) {
+            if len != resp.body.len() {
+                return Err(format!("truncated response: Content-Length {} but received {} bytes", len, resp.body.len()));
+            }
+        }
+    }
+    if let Some(expected) = header(&resp.headers, CONTENT_SHA256_HEADER) {
+        let actual = sha256_hex(resp.body.as_bytes());
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(format!("response checksum mismatch: {} {} but body hashes to {}", CONTENT_SHA256_HEADER, expected, actual));
+        }
+    }
+    Ok(())
+}
+
+/// SHA-256 (FIPS 180-4) as lowercase hex; the sandbox has no crypto crate.
+fn sha256_hex(data: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (x, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *x = x.wrapping_add(v);
+        }
+    }
+
+    h.iter().map(|x| format!("{:08x}", x)).collect()
+}
+
+/// Resolve a `Location` header (absolute, `//host`, `/path`, `?query` or a
+/// relative path) against the URL that returned it. Dot segments are kept.
+fn resolve_location(current: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    if let Some(rest) = location.strip_prefix("//") {
+        let scheme = current.split_once("://").map(|(s, _)| s).unwrap_or("https");
+        return format!("{}://{}", scheme, rest);
+    }
+    if location.starts_with('/') {
+        return format!("{}{}", url_origin(current), location);
+    }
+    let path = current.split(['?', '#']).next().unwrap_or(current);
+    if location.starts_with('?') {
+        return format!("{}{}", path, location);
+    }
+    let dir = match path.rfind('/') {
+        Some(i) if i >= url_origin(current).len() => &path[..=i],
+        _ => return format!("{}/{}", url_origin(current), location),
+    };
+    format!("{}{}", dir, location)
+}
+
 impl SustainalyticsFdw {
-    fn normalize_take(raw: Option<String>) -> i64 {
-        let Some(s) = raw else { return DEFAULT_TAKE; };
-        let Ok(n) = s.parse::<i64>() else { return DEFAULT_TAKE; };
-        if n < 1 { return DEFAULT_TAKE; }
+    fn normalize_take(n: i64) -> i64 {
         if n < MAX_TAKE { n } else { MAX_TAKE }
     }
 
-    fn build_dataservices_url(&self, p: &DataServicesParams, skip: i64) -> String {
+    /// Build the pager selected by the `paging` table option.
+    fn build_pager(topts: &TypedOptions, take: i64) -> FdwResult<Paging> {
+        match topts.require("paging")?.as_str() {
+            "skip_take" => Ok(Paging::SkipTake(SkipTakePager {
+                skip: topts.require_int("Skip")?,
+                take,
+                total: None,
+                last_first_row: None,
+                done: false,
+            })),
+            "keyset" => Ok(Paging::Keyset(KeysetPager {
+                take,
+                key_field: topts.require("keyset_field")?,
+                param: topts.require("keyset_param")?,
+                last_key: None,
+                done: false,
+            })),
+            "token" => Ok(Paging::Token(TokenPager {
+                take,
+                param: topts.require("token_param")?,
+                field: topts.require("token_field")?,
+                token: None,
+                done: false,
+            })),
+            _ => Ok(Paging::SingleShot(SingleShotPager { done: false })),
+        }
+    }
+
+    /// Ids from `entityId = ...` / `entityId IN (...)` conditions, deduplicated
+    /// in query order.
+    fn entity_id_quals(ctx: &Context) -> Result<Vec<String>, String> {
+        let mut ids: Vec<String> = Vec::new();
+        for qual in ctx.get_quals() {
+            if qual.field() != "entityId" || qual.operator() != "=" {
+                continue;
+            }
+            let cells = match qual.value() {
+                Value::Cell(c) => vec![c],
+                Value::Array(cs) => cs,
+            };
+            for c in &cells {
+                let id = match c {
+                    Cell::String(s) => s.clone(),
+                    Cell::I64(n) => n.to_string(),
+                    Cell::I32(n) => n.to_string(),
+                    other => return Err(format!("unsupported entityId value {:?}", other)),
+                };
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Build the per-entity pager from the `entityId` conditions, grouping
+    /// `entities_per_request` ids into each request.
+    fn build_entity_pager(topts: &TypedOptions, ids: Vec<String>) -> FdwResult<Paging> {
+        if ids.is_empty() {
+            return Err("request_mode 'per_entity' needs a WHERE entityId = ... or entityId IN (...) condition".into());
+        }
+
+        let per_request = topts.require_int("entities_per_request")? as usize;
+        Ok(Paging::Entity(EntityPager {
+            param: topts.require("entity_param")?,
+            batches: ids.chunks(per_request).map(|c| c.join(",")).collect(),
+            next: 0,
+        }))
+    }
+
+    fn build_dataservices_url(&self, p: &DataServicesParams, pager: &dyn Pager) -> String {
         let base = self.base_url.trim_end_matches('/');
 
-        let mut parts: Vec<String> = vec![
-            format!("ProductId={}", encode(&p.product_id)),
-            format!("Skip={}", skip),
-            format!("Take={}", p.take),
-        ];
+        let mut parts: Vec<String> = vec![format!("ProductId={}", encode(&p.product_id))];
+        for (k, v) in pager.query_params() {
+            parts.push(format!("{}={}", k, encode(&v)));
+        }
 
         if let Some(v) = &p.package_ids {
             parts.push(format!("PackageIds={}", encode(v)));
@@ -116,8 +1790,59 @@ impl SustainalyticsFdw {
         if let Some(v) = &p.field_ids {
             parts.push(format!("FieldIds={}", encode(v)));
         }
+        if let Some(v) = &p.as_of {
+            parts.push(format!("AsOf={}", encode(v)));
+        }
+
+        format!("{}/v2/DataService?{}", base, parts.join("&"))
+    }
+
+    /// Send a request, following up to `max_redirects` redirects. The
+    /// authorization header and a POST body are only carried to the origin of
+    /// the original request; a POST is only replayed on 307/308. Under
+    /// `verify_responses`, a 2xx body failing `verify_body` is fetched again.
+    fn send(&mut self, mut req: http::Request) -> FdwResult<http::Response> {
+        let origin = url_origin(&req.url);
+        let mut redirects = 0;
+        let mut retries = 0;
+        loop {
+            self.http_requests += 1;
+            let resp = match req.method {
+                http::Method::Post => http::post(&req)?,
+                _ => http::get(&req)?,
+            };
+            if !matches!(resp.status_code, 301 | 302 | 303 | 307 | 308) {
+                if !self.verify_responses || !(200..300).contains(&resp.status_code) {
+                    return Ok(resp);
+                }
+                match verify_body(&resp) {
+                    Ok(()) => return Ok(resp),
+                    Err(e) if retries < MAX_VERIFY_RETRIES => {
+                        retries += 1;
+                        utils::report_warning(&format!("{}: {}, retrying ({}/{}) url={}", FDW_NAME, e, retries, MAX_VERIFY_RETRIES, req.url));
+                        continue;
+                    }
+                    Err(e) => return Err(format!("{} after {} attempts, url={}", e, retries + 1, req.url).into()),
+                }
+            }
+            let Some(location) = header(&resp.headers, "location") else {
+                return Err(format!("redirect without Location header: status={} url={}", resp.status_code, req.url).into());
+            };
+            if redirects >= self.max_redirects {
+                return Err(format!("too many redirects (max_redirects = {}), last url={}", self.max_redirects, req.url).into());
+            }
+            redirects += 1;
 
-        format!("{}/v2/DataService?{}", base, parts.join("&"))
+            let next = resolve_location(&req.url, location);
+            let same_origin = url_origin(&next) == origin;
+            if matches!(req.method, http::Method::Post) && (!matches!(resp.status_code, 307 | 308) || !same_origin) {
+                return Err(format!("refusing to follow redirect of POST {} to {}", req.url, next).into());
+            }
+            if !same_origin {
+                req.headers.retain(|(k, _)| !k.eq_ignore_ascii_case("authorization"));
+            }
+            req.url = next;
+        }
     }
 
     fn fetch_token(&mut self) -> FdwResult<String> {
@@ -139,7 +1864,7 @@ impl SustainalyticsFdw {
             body,
         };
 
-        let resp = http::post(&req)?;
+        let resp = self.send(req)?;
         if !(200..300).contains(&resp.status_code) {
             return Err(format!("auth failed: status={} body={}", resp.status_code, resp.body).into());
         }
@@ -148,14 +1873,16 @@ impl SustainalyticsFdw {
             .map_err(|e| format!("invalid auth json: {e}"))?;
 
         self.cached_token = Some(tr.access_token.clone());
+        self.token_expires_at = Some(time::epoch_secs() + tr.expires_in);
         Ok(tr.access_token)
     }
 
     fn ensure_token(&mut self) -> FdwResult<String> {
-        if let Some(tok) = &self.cached_token {
-            return Ok(tok.clone());
+        let fresh = self.token_expires_at.is_none_or(|t| time::epoch_secs() < t - TOKEN_EXPIRY_MARGIN_SECS);
+        match &self.cached_token {
+            Some(tok) if fresh => Ok(tok.clone()),
+            _ => self.fetch_token(),
         }
-        self.fetch_token()
     }
 
     fn get_json_with_bearer(&mut self, url: &str) -> FdwResult<(i32, JsonValue)> {
@@ -170,8 +1897,11 @@ impl SustainalyticsFdw {
             body: String::new(),
         };
 
-        let resp = http::get(&req)?;
+        let resp = self.send(req)?;
         let status = resp.status_code;
+        if let Some(v) = header(&resp.headers, CATALOG_VERSION_HEADER) {
+            self.catalog_version.observe(v);
+        }
 
         if status == 401 || status == 403 {
             let _ = self.fetch_token()?;
@@ -185,79 +1915,259 @@ impl SustainalyticsFdw {
                 ],
                 body: String::new(),
             };
-            let resp2 = http::get(&req2)?;
-            let v2: JsonValue = serde_json::from_str(&resp2.body)
-                .map_err(|e| format!("invalid json: {e}"))?;
+            let resp2 = self.send(req2)?;
+            if let Some(v) = header(&resp2.headers, CATALOG_VERSION_HEADER) {
+                self.catalog_version.observe(v);
+            }
+            let v2 = parse_json(&resp2.body)?;
+            self.observe_body_catalog_version(&v2);
             return Ok((resp2.status_code, v2));
         }
 
-        let v: JsonValue = serde_json::from_str(&resp.body)
-            .map_err(|e| format!("invalid json: {e}"))?;
+        let v = parse_json(&resp.body)?;
+        self.observe_body_catalog_version(&v);
         Ok((status, v))
     }
 
+    fn observe_body_catalog_version(&mut self, json: &JsonValue) {
+        if let Some(v) = attr(json, "catalogVersion") {
+            self.catalog_version.observe(&json_value_text(v));
+        }
+    }
+
+    /// Drop the cached catalog once a version change is due for a refresh.
+    fn invalidate_stale_catalog(&mut self) {
+        if self.catalog_version.take_due(time::epoch_secs()) {
+            self.cached_catalog = None;
+            utils::report_info(&format!(
+                "{}: catalog version changed to {}, refreshing FieldMappingDefinitions",
+                FDW_NAME,
+                self.catalog_version.seen.as_deref().unwrap_or_default(),
+            ));
+        }
+    }
+
+    /// Rebuild the scan's catalog-derived column plans after an invalidation.
+    fn refresh_column_plans(&mut self, scan: &mut DataServicesScan) -> FdwResult<()> {
+        self.invalidate_stale_catalog();
+        if scan.catalog_generation == self.catalog_version.generation {
+            return Ok(());
+        }
+        scan.catalog_generation = self.catalog_version.generation;
+        scan.converter.schema_checked.clear();
+        if scan.converter.validate_values != ValueCheck::Off {
+            self.attach_value_domains(&scan.params.product_id, &mut scan.converter.columns)?;
+        }
+        Ok(())
+    }
+
     fn load_dataservices_page(&mut self, scan: &mut DataServicesScan) -> FdwResult<()> {
-        if scan.done {
+        if scan.paging.pager().is_done() {
             return Ok(());
         }
 
-        let url = self.build_dataservices_url(&scan.params, scan.skip);
+        if let Some(limit) = scan.request_limit.filter(|l| scan.requests >= *l) {
+            return Err(format!(
+                "DataServices scan reached confirm_requests_over {} API requests without finishing; \
+                 narrow it with PackageIds/FieldClusterIds/FieldIds or a WHERE entityId condition, \
+                 or set table option allow_large_scan 'true'",
+                limit
+            ).into());
+        }
+        if scan.requests > 0 && scan.throttle_ms > 0 {
+            time::sleep(scan.throttle_ms);
+        }
+        scan.requests += 1;
+
+        let url = self.build_dataservices_url(&scan.params, scan.paging.pager());
         let (status, json) = self.get_json_with_bearer(&url)?;
         if !(200..300).contains(&status) {
             return Err(format!("DataServices failed: status={} url={}", status, url).into());
         }
 
-        let arr = json.as_array().ok_or("DataServices response not an array")?.to_vec();
+        let arr = Self::page_rows(&json).ok_or("DataServices response has no row array")?.to_vec();
+        Self::check_snapshot(scan, &json)?;
+        scan.paging.pager_mut().advance(&json, &arr)?;
         scan.page_rows = arr;
         scan.page_idx = 0;
+        scan.converter.fetched_at = time::epoch_secs();
+        self.refresh_column_plans(scan)?;
 
-        if (scan.page_rows.len() as i64) < scan.params.take {
-            scan.done = true;
-        } else {
-            scan.skip += scan.params.take;
-        }
+        Ok(())
+    }
 
+    /// Fail a scan whose estimated request count exceeds `confirm_requests_over`
+    /// rather than let an ad-hoc query pull the whole universe. Scans the
+    /// pager can't size pass here and are stopped by `load_dataservices_page`
+    /// once they reach the limit.
+    fn check_request_estimate(scan: &DataServicesScan) -> FdwResult<()> {
+        let Some(limit) = scan.request_limit else { return Ok(()); };
+        let Some(remaining) = scan.paging.pager().remaining_requests() else { return Ok(()); };
+
+        // A backfill repeats the scan per date; assume each date is as large.
+        let dates = scan.backfill.as_ref().map_or(0, |b| b.dates.len() as u64);
+        let estimate = (scan.requests + remaining).saturating_mul(dates + 1);
+        if estimate > limit {
+            return Err(format!(
+                "DataServices scan needs an estimated {} API requests, above confirm_requests_over {}; \
+                 narrow it with PackageIds/FieldClusterIds/FieldIds or a WHERE entityId condition, \
+                 or set table option allow_large_scan 'true'",
+                estimate,
+                limit
+            ).into());
+        }
         Ok(())
     }
 
+    /// Rows of a page: either the whole body, or the `data`/`items` array of an
+    /// envelope object (token-paged endpoints wrap rows next to the token).
+    fn page_rows(json: &JsonValue) -> Option<&Vec<JsonValue>> {
+        if let Some(arr) = json.as_array() {
+            return Some(arr);
+        }
+        attr(json, "data")
+            .or_else(|| attr(json, "items"))
+            .and_then(|v| v.as_array())
+    }
+
     fn ensure_dataservices_rows(&mut self, scan: &mut DataServicesScan) -> FdwResult<()> {
+        // A page may legitimately come back empty (e.g. keyset end), so keep
+        // fetching until rows arrive or the pager (and any backfill) is exhausted.
+        while scan.page_idx >= scan.page_rows.len() {
+            if !scan.paging.pager().is_done() {
+                self.load_dataservices_page(scan)?;
+            } else if !Self::next_as_of(scan) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move a backfill to its next date, restarting the pager. False once
+    /// every date has been scanned (or the scan is no backfill).
+    fn next_as_of(scan: &mut DataServicesScan) -> bool {
+        let Some(backfill) = scan.backfill.as_mut() else { return false; };
+        let Some(date) = backfill.dates.pop_front() else { return false; };
+        scan.paging = backfill.paging.clone();
+        scan.params.as_of = Some(format_date(date));
+        scan.converter.as_of = Some(date);
+        true
+    }
+
+    /// Convert the next API row, fetching the next page once the current one
+    /// is used up. Raw JSON is released as soon as a row is converted.
+    fn fill_dataservices_buffer(&mut self, scan: &mut DataServicesScan) -> FdwResult<()> {
+        self.ensure_dataservices_rows(scan)?;
         if scan.page_idx < scan.page_rows.len() {
-            return Ok(());
+            let src = std::mem::take(&mut scan.page_rows[scan.page_idx]);
+            scan.page_idx += 1;
+            scan.ready.extend(scan.converter.convert(&src)?);
         }
-        if scan.done {
-            return Ok(());
+        Ok(())
+    }
+
+    fn iter_dataservices(&mut self, row: &Row, scan: &mut DataServicesScan) -> FdwResult<Option<u32>> {
+        if scan.ready.is_empty() {
+            self.fill_dataservices_buffer(scan)?;
         }
-        self.load_dataservices_page(scan)
+
+        let Some(cells) = scan.ready.pop_front() else {
+            return self.finish_dataservices(row, scan);
+        };
+        for cell in &cells {
+            row.push(cell.as_ref());
+        }
+        scan.rows_emitted += 1;
+        Ok(Some(0))
+    }
+
+    /// Report the scan totals once the last row is out, as a synthetic row
+    /// (not counted in `rows_emitted`) or a notice.
+    fn finish_dataservices(&mut self, row: &Row, scan: &mut DataServicesScan) -> FdwResult<Option<u32>> {
+        let mode = std::mem::replace(&mut scan.summary_row, SummaryRow::Done);
+        if matches!(mode, SummaryRow::Off | SummaryRow::Done) {
+            scan.summary_row = mode;
+            return Ok(None);
+        }
+
+        let duration = time::epoch_secs() - scan.started_at;
+        let requests = self.http_requests - scan.http_requests_at_start;
+        let text = format!(
+            "{} summary: rows={} pages={} requests={} duration_secs={}",
+            FDW_NAME, scan.rows_emitted, scan.requests, requests, duration
+        );
+        if mode == SummaryRow::Notice {
+            utils::report_notice(&text);
+            return Ok(None);
+        }
+
+        let mut totals = serde_json::Map::new();
+        totals.insert("rows".to_string(), JsonValue::from(scan.rows_emitted));
+        totals.insert("pages".to_string(), JsonValue::from(scan.requests));
+        totals.insert("requests".to_string(), JsonValue::from(requests));
+        totals.insert("duration_secs".to_string(), JsonValue::from(duration));
+        for col in &scan.converter.columns {
+            let cell = match col.name.as_str() {
+                "entityId" => Some(Cell::String("_summary".to_string())),
+                "entityName" => Some(Cell::String(text.clone())),
+                "fields" => Some(Cell::Jsonb(JsonValue::Object(totals.clone()).to_string())),
+                "_fetched_at" => Some(timestamp_cell(time::epoch_secs(), &col.name, col.type_oid, self.report_offset)?),
+                _ => None,
+            };
+            row.push(cell.as_ref());
+        }
+        Ok(Some(0))
     }
 
     fn load_field_mapping_definitions(&mut self) -> FdwResult<Vec<FieldMappingRow>> {
         let url = format!("{}/v2/FieldMappingDefinitions", self.base_url.trim_end_matches('/'));
-        let (status, json) = self.get_json_with_bearer(&url)?;
+        let (status, json) = self.get_json_with_bearer(&url).map_err(|e| match e.to_string() {
+            e if e.contains(JSON_TOO_DEEP) => format!(
+                "FieldMappingDefinitions {}; a field's parentage is likely nested too deep to load at all (only nesting \
+                 between {} and {} levels can be truncated)",
+                e, MAX_PARENTAGE_DEPTH, MAX_JSON_DEPTH
+            ),
+            e => e,
+        })?;
         if !(200..300).contains(&status) {
             return Err(format!("FieldMappingDefinitions failed: status={} body={}", status, json).into());
         }
 
+        let out = SustainalyticsFdw::parse_field_mapping_definitions(&json)?;
+        // A version change announced by this very response is now loaded.
+        self.catalog_version.pending = false;
+        Ok(out)
+    }
+
+    /// Flatten a FieldMappingDefinitions response into one row per field definition.
+    fn parse_field_mapping_definitions(json: &JsonValue) -> FdwResult<Vec<FieldMappingRow>> {
         let products = json.as_array().ok_or("FieldMappingDefinitions not an array")?;
         let mut out: Vec<FieldMappingRow> = Vec::new();
+        let mut truncated = 0;
 
         for prod in products {
-            let product_id = prod.get("productId")
+            let product_id = attr(prod, "productId")
                 .map(|v| v.to_string().trim_matches('\"').to_string())
                 .unwrap_or_default();
-            let product_name = prod.get("productName").and_then(|v| v.as_str()).map(|s| s.to_string());
+            let product_name = attr(prod, "productName").and_then(|v| v.as_str()).map(|s| s.to_string());
 
-            let packages = prod.get("packages").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+            let packages = attr(prod, "packages").and_then(|v| v.as_array()).cloned().unwrap_or_default();
             for pkg in packages {
-                let package_id = pkg.get("packageId").and_then(|v| v.as_i64());
-                let package_name = pkg.get("packageName").and_then(|v| v.as_str()).map(|s| s.to_string());
+                let package_id = attr(&pkg, "packageId").and_then(|v| v.as_i64());
+                let package_name = attr(&pkg, "packageName").and_then(|v| v.as_str()).map(|s| s.to_string());
 
-                let clusters = pkg.get("clusters").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+                let clusters = attr(&pkg, "clusters").and_then(|v| v.as_array()).cloned().unwrap_or_default();
                 for cl in clusters {
-                    let field_cluster_id = cl.get("fieldClusterId").and_then(|v| v.as_i64());
-                    let field_cluster_name = cl.get("fieldClusterName").and_then(|v| v.as_str()).map(|s| s.to_string());
+                    let field_cluster_id = attr(&cl, "fieldClusterId").and_then(|v| v.as_i64());
+                    let field_cluster_name = attr(&cl, "fieldClusterName").and_then(|v| v.as_str()).map(|s| s.to_string());
 
-                    let defs = cl.get("fieldDefinitions").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+                    let defs = attr(&cl, "fieldDefinitions").and_then(|v| v.as_array()).cloned().unwrap_or_default();
                     for d in defs {
+                        let parentage = attr(&d, "parentage").map(|p| {
+                            let (p, cut) = truncate_depth(p, MAX_PARENTAGE_DEPTH);
+                            truncated += usize::from(cut);
+                            p
+                        });
                         out.push(FieldMappingRow {
                             product_id: product_id.clone(),
                             product_name: product_name.clone(),
@@ -265,22 +2175,338 @@ impl SustainalyticsFdw {
                             package_name: package_name.clone(),
                             field_cluster_id,
                             field_cluster_name: field_cluster_name.clone(),
-                            field_id: d.get("fieldId").and_then(|v| v.as_i64()),
-                            field_name: d.get("fieldName").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                            description: d.get("description").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                            field_type: d.get("fieldType").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                            field_length: d.get("fieldLength").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                            possible_values: d.get("possibleValues").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                            grouping: d.get("grouping").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                            parentage: d.get("parentage").cloned(),
+                            field_id: attr(&d, "fieldId").and_then(|v| v.as_i64()),
+                            field_name: attr(&d, "fieldName").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                            description: attr(&d, "description").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                            field_type: attr(&d, "fieldType").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                            field_length: attr(&d, "fieldLength").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                            possible_values: attr(&d, "possibleValues").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                            grouping: attr(&d, "grouping").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                            parentage,
                         });
                     }
                 }
             }
         }
 
+        if truncated > 0 {
+            utils::report_warning(&format!(
+                "{}: {} field definitions have parentage nested deeper than {} levels; deeper levels are returned as null",
+                FDW_NAME, truncated, MAX_PARENTAGE_DEPTH
+            ));
+        }
         Ok(out)
     }
+
+    fn ensure_catalog(&mut self) -> FdwResult<&[FieldMappingRow]> {
+        self.invalidate_stale_catalog();
+        if self.cached_catalog.is_none() {
+            self.cached_catalog = Some(self.load_field_mapping_definitions()?);
+        }
+        Ok(self.cached_catalog.as_deref().unwrap_or_default())
+    }
+
+    /// Check PackageIds/FieldClusterIds/FieldIds against the catalog of the
+    /// scanned product, listing every id the catalog doesn't know. Unknown ids
+    /// are otherwise accepted by the API and just yield empty `fields`.
+    fn validate_against_catalog(&mut self, p: &DataServicesParams) -> FdwResult<()> {
+        let catalog = self.ensure_catalog()?;
+        let product: Vec<&FieldMappingRow> = catalog.iter().filter(|r| r.product_id == p.product_id).collect();
+        if product.is_empty() {
+            return Err(format!("validate_options: ProductId {} not found in FieldMappingDefinitions", p.product_id).into());
+        }
+
+        let mut problems: Vec<String> = Vec::new();
+        let mut check = |option: &str, value: &Option<String>, id_of: fn(&FieldMappingRow) -> Option<i64>| {
+            let Some(list) = value else { return; };
+            let unknown: Vec<&str> = list
+                .split(',')
+                .map(|id| id.trim())
+                .filter(|id| !id.is_empty())
+                .filter(|id| !product.iter().any(|r| id_of(r).map(|n| n.to_string()).as_deref() == Some(*id)))
+                .collect();
+            if !unknown.is_empty() {
+                problems.push(format!("{} [{}]", option, unknown.join(", ")));
+            }
+        };
+        check("PackageIds", &p.package_ids, |r| r.package_id);
+        check("FieldClusterIds", &p.field_cluster_ids, |r| r.field_cluster_id);
+        check("FieldIds", &p.field_ids, |r| r.field_id);
+
+        if !problems.is_empty() {
+            return Err(format!(
+                "validate_options: unknown ids for ProductId {}: {}",
+                p.product_id,
+                problems.join("; ")
+            ).into());
+        }
+        Ok(())
+    }
+
+    /// Give each mapped column the `possibleValues` of its field in the catalog.
+    fn attach_value_domains(&mut self, product_id: &str, columns: &mut [ColumnSpec]) -> FdwResult<()> {
+        let catalog = self.ensure_catalog()?;
+        for col in columns.iter_mut() {
+            let Some(id) = col.field_id else { continue; };
+            col.domain = catalog
+                .iter()
+                .find(|r| r.product_id == product_id && r.field_id == Some(id))
+                .and_then(|r| r.possible_values.as_deref())
+                .and_then(parse_possible_values);
+        }
+        Ok(())
+    }
+
+    fn iter_field_mapping_definitions(ctx: &Context, row: &Row, scan: &mut FieldMappingDefinitionsScan) -> FdwResult<Option<u32>> {
+        if scan.idx >= scan.rows.len() {
+            return Ok(None);
+        }
+
+        let r = &scan.rows[scan.idx];
+
+        for col in ctx.get_columns() {
+            let cell = match r.cell(col.name(), col.type_oid(), scan.fetched_at, scan.report_offset) {
+                Some(cell) => cell?,
+                None if scan.future.iter().any(|f| f == col.name()) => None,
+                None => return Err(format!("unsupported column for FieldMappingDefinitions: {}", col.name()).into()),
+            };
+            row.push(cell.as_ref());
+        }
+
+        scan.idx += 1;
+        Ok(Some(0))
+    }
+
+    /// Key for per-tenant entries of the FDW stats metadata, which every foreign
+    /// server shares: the gateway and the OAuth client, never the secret.
+    fn tenant_key(&self) -> String {
+        format!("{} client_id={}", self.base_url.trim_end_matches('/'), self.client_id)
+    }
+
+    /// Record a full scan that ran to completion in the FDW stats metadata
+    /// (`wrappers_fdw_stats.metadata -> 'last_sync'`), keyed by tenant and then
+    /// `endpoint` or `endpoint/ProductId`. The metadata is shared by every
+    /// backend and server using the FDW, so only this scan's entry is replaced.
+    fn record_sync(&self, state: &ScanState) {
+        let (name, rows) = match state {
+            ScanState::DataServices(scan) if scan.full_scan => (format!("DataServices/{}", scan.params.product_id), scan.rows_emitted),
+            ScanState::DataServices(_) => return,
+            ScanState::FieldMappingDefinitions(scan) => ("FieldMappingDefinitions".to_string(), scan.idx as u64),
+            ScanState::Options(_) | ScanState::Health(_) | ScanState::None => return,
+        };
+        let finished_at = time::epoch_secs();
+
+        let mut entry = serde_json::Map::new();
+        entry.insert("finished_at".to_string(), JsonValue::from(finished_at));
+        entry.insert("finished_at_text".to_string(), JsonValue::from(format_rfc3339(finished_at, self.report_offset)));
+        entry.insert("rows".to_string(), JsonValue::from(rows));
+
+        let tenant = self.tenant_key();
+        let mut syncs = metadata_key("last_sync").and_then(|v| v.as_object().cloned()).unwrap_or_default();
+        let mut tenant_syncs = syncs.get(&tenant).and_then(|v| v.as_object().cloned()).unwrap_or_default();
+        tenant_syncs.insert(name, JsonValue::Object(entry));
+        syncs.insert(tenant, JsonValue::Object(tenant_syncs));
+        set_metadata_key("last_sync", JsonValue::Object(syncs));
+    }
+
+    /// The `AsOf` another fenced scan of the product recorded within the last
+    /// `window` seconds. The stats metadata is the only state shared between
+    /// the FDW instances of the tables in a statement.
+    fn fenced_as_of(product_id: &str, window: i64) -> Option<String> {
+        let fence = metadata_key("snapshots")?.get(product_id)?.clone();
+        let recorded_at = fence.get("recorded_at")?.as_i64()?;
+        if time::epoch_secs() - recorded_at > window {
+            return None;
+        }
+        fence.get("as_of")?.as_str().map(|s| s.to_string())
+    }
+
+    /// Hold every page of a fenced scan to one upstream snapshot: the first
+    /// page's `asOf` is pinned (and published for the product's other tables)
+    /// and any later page reporting another refresh fails the scan.
+    fn check_snapshot(scan: &mut DataServicesScan, json: &JsonValue) -> FdwResult<()> {
+        if scan.snapshot_fence_secs == 0 {
+            return Ok(());
+        }
+        let Some(as_of) = attr(json, "asOf").map(json_value_text) else { return Ok(()); };
+        let product_id = &scan.params.product_id;
+        match &scan.params.as_of {
+            Some(pinned) if *pinned != as_of => Err(format!(
+                "snapshot_fence: ProductId {} changed upstream refresh mid-statement (asOf {} -> {}); rerun the query",
+                product_id, pinned, as_of
+            ).into()),
+            Some(_) => Ok(()),
+            None => {
+                let mut snapshots = metadata_key("snapshots").and_then(|v| v.as_object().cloned()).unwrap_or_default();
+                let mut fence = serde_json::Map::new();
+                fence.insert("as_of".to_string(), JsonValue::from(as_of.clone()));
+                fence.insert("recorded_at".to_string(), JsonValue::from(time::epoch_secs()));
+                snapshots.insert(product_id.clone(), JsonValue::Object(fence));
+                set_metadata_key("snapshots", JsonValue::Object(snapshots));
+                scan.params.as_of = Some(as_of);
+                Ok(())
+            }
+        }
+    }
+
+    /// Options and Health are served by the FDW itself and always available.
+    fn endpoint_available(&self, endpoint: &str) -> bool {
+        !DATA_ENDPOINTS.contains(&endpoint)
+            || self.available_endpoints.as_ref().is_none_or(|l| l.iter().any(|e| e == endpoint))
+    }
+
+    /// Detect the data endpoints this tenant can use. A 403/404/405/501 marks
+    /// an endpoint missing; anything else (even a 400 for the absent
+    /// ProductId) means it is there. Probe failures assume availability, so a
+    /// flaky gateway doesn't hide endpoints. Results are shared through the
+    /// stats metadata for `CAPABILITY_TTL_SECS` per tenant, so not every
+    /// connection probes.
+    /// The FieldMappingDefinitions probe body is kept as the catalog.
+    fn probe_endpoints(&mut self) -> Vec<String> {
+        let now = time::epoch_secs();
+        let tenant = self.tenant_key();
+        let cached = metadata_key("capabilities").and_then(|c| c.get(&tenant).cloned());
+        if let Some(c) = cached.filter(|c| c.get("probed_at").and_then(|t| t.as_i64()).is_some_and(|t| now - t <= CAPABILITY_TTL_SECS)) {
+            if let Some(list) = c.get("endpoints").and_then(|e| e.as_array()) {
+                return list.iter().filter_map(|e| e.as_str().map(|s| s.to_string())).collect();
+            }
+        }
+
+        let mut available = Vec::new();
+        for (endpoint, path) in ENDPOINT_PATHS {
+            let url = format!("{}{}", self.base_url.trim_end_matches('/'), path);
+            // Only the status matters; error pages needn't be JSON.
+            let resp = self.ensure_token().and_then(|token| {
+                self.send(http::Request {
+                    method: http::Method::Get,
+                    url,
+                    headers: vec![
+                        ("accept".to_owned(), "application/json".to_owned()),
+                        ("authorization".to_owned(), format!("Bearer {}", token)),
+                    ],
+                    body: String::new(),
+                })
+            });
+            if let Ok(r) = &resp {
+                if *endpoint == "FieldMappingDefinitions" && (200..300).contains(&r.status_code) {
+                    self.adopt_catalog(r);
+                }
+            }
+            match resp.map(|r| r.status_code) {
+                Ok(403 | 404 | 405 | 501) => {}
+                Ok(_) => available.push(endpoint.to_string()),
+                Err(e) => {
+                    utils::report_warning(&format!("{}: probing {} failed ({}); assuming it is available", FDW_NAME, endpoint, e));
+                    available.push(endpoint.to_string());
+                }
+            }
+        }
+
+        let mut capabilities = metadata_key("capabilities").and_then(|v| v.as_object().cloned()).unwrap_or_default();
+        let mut entry = serde_json::Map::new();
+        entry.insert("endpoints".to_string(), JsonValue::Array(available.iter().map(|e| JsonValue::from(e.clone())).collect()));
+        entry.insert("probed_at".to_string(), JsonValue::from(now));
+        capabilities.insert(tenant, JsonValue::Object(entry));
+        set_metadata_key("capabilities", JsonValue::Object(capabilities));
+        available
+    }
+
+    /// Keep a probed FieldMappingDefinitions body as the catalog, so the probe
+    /// doesn't cost a second download of it. A body that doesn't parse is
+    /// left for `ensure_catalog` to fetch (and report) when needed.
+    fn adopt_catalog(&mut self, resp: &http::Response) {
+        if let Some(v) = header(&resp.headers, CATALOG_VERSION_HEADER) {
+            self.catalog_version.observe(v);
+        }
+        let parsed = serde_json::from_str::<JsonValue>(&resp.body)
+            .ok()
+            .and_then(|json| SustainalyticsFdw::parse_field_mapping_definitions(&json).ok());
+        if let Some(rows) = parsed {
+            self.cached_catalog = Some(rows);
+            self.catalog_version.pending = false;
+        }
+    }
+
+    /// Probe the API with one bearer-authenticated DataService GET, reusing
+    /// the cached token while it is valid. The request leaves out ProductId,
+    /// so no data is returned: the gateway authenticates before the API
+    /// validates parameters, and its 400 for the missing ProductId shows the
+    /// token and routing work. A rejected token is replaced once. The host
+    /// clock only has second resolution, so latency is whole seconds.
+    fn check_health(&mut self) -> HealthRow {
+        let started = time::epoch_secs();
+        let url = format!("{}/v2/DataService", self.base_url.trim_end_matches('/'));
+        let mut result = self.authenticated_status(&url);
+        if let Ok(401) = result {
+            result = self.fetch_token().and_then(|_| self.authenticated_status(&url));
+        }
+        let result = result.and_then(|status| match status {
+            200..=299 | 400 => Ok(()),
+            other => Err(format!("DataService answered status {}", other).into()),
+        });
+        let finished = time::epoch_secs();
+
+        HealthRow {
+            ok: result.is_ok(),
+            checked_at: finished,
+            latency_secs: finished - started,
+            token_expires_at: result.as_ref().ok().and(self.token_expires_at),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+
+    fn authenticated_status(&mut self, url: &str) -> FdwResult<i32> {
+        let token = self.ensure_token()?;
+        let resp = self.send(http::Request {
+            method: http::Method::Get,
+            url: url.to_string(),
+            headers: vec![
+                ("accept".to_owned(), "application/json".to_owned()),
+                ("authorization".to_owned(), format!("Bearer {}", token)),
+            ],
+            body: String::new(),
+        })?;
+        Ok(resp.status_code)
+    }
+
+    fn iter_health(ctx: &Context, row: &Row, scan: &mut HealthScan) -> FdwResult<Option<u32>> {
+        let Some(h) = scan.row.take() else { return Ok(None); };
+
+        for col in ctx.get_columns() {
+            let cell = match col.name() {
+                "status" => Some(Cell::String(if h.ok { "ok" } else { "error" }.to_string())),
+                "checked_at" => Some(timestamp_cell(h.checked_at, col.name(), col.type_oid(), scan.report_offset)?),
+                "latency_secs" => Some(Cell::I64(h.latency_secs)),
+                "token_expires_at" => h.token_expires_at.map(|t| timestamp_cell(t, col.name(), col.type_oid(), scan.report_offset)).transpose()?,
+                "error" => h.error.clone().map(Cell::String),
+                other => return Err(format!("unsupported column for Health: {}", other).into()),
+            };
+            row.push(cell.as_ref());
+        }
+
+        Ok(Some(0))
+    }
+
+    fn iter_options(ctx: &Context, row: &Row, scan: &mut OptionsScan) -> FdwResult<Option<u32>> {
+        let Some(o) = OPTION_REGISTRY.get(scan.idx) else { return Ok(None); };
+
+        for col in ctx.get_columns() {
+            let cell = match col.name() {
+                "scope" => Some(Cell::String(o.scope.as_str().to_string())),
+                "name" => Some(Cell::String(o.name.to_string())),
+                "endpoints" => Some(Cell::String(o.endpoints.join(", "))).filter(|_| !o.endpoints.is_empty()),
+                "available" => Some(Cell::Bool(o.endpoints.is_empty() || o.endpoints.iter().any(|e| !scan.unavailable.contains(e)))),
+                "type" => Some(Cell::String(o.kind.type_name())),
+                "default_value" => o.default.map(|d| Cell::String(d.to_string())),
+                "description" => Some(Cell::String(o.description.to_string())),
+                other => return Err(format!("unsupported column for Options: {}", other).into()),
+            };
+            row.push(cell.as_ref());
+        }
+
+        scan.idx += 1;
+        Ok(Some(0))
+    }
 }
 
 static INSTANCE: std::sync::Mutex<Option<SustainalyticsFdw>> = std::sync::Mutex::new(None);
@@ -288,19 +2514,48 @@ static INSTANCE: std::sync::Mutex<Option<SustainalyticsFdw>> = std::sync::Mutex:
 #[supabase_wrappers::fdw]
 impl ForeignDataWrapper for SustainalyticsFdw {
     fn init(ctx: &Context) -> FdwResult<()> {
-        let sopts = ctx.get_options(OptionsType::Server);
-
-        let base_url = sopts.get("base_url").unwrap_or(DEFAULT_BASE_URL).to_string();
-        let client_id = sopts.get("client_id").ok_or("missing server option client_id")?.to_string();
-        let client_secret = sopts.get("client_secret").ok_or("missing server option client_secret")?.to_string();
-
-        let fdw = SustainalyticsFdw {
+        let raw = ctx.get_options(OptionsType::Server);
+        let sopts = TypedOptions::new(OptionScope::Server, &raw);
+        sopts.validate_all()?;
+
+        let base_url = sopts.require("base_url")?;
+        let client_id = sopts.require("client_id")?;
+        let client_secret = sopts.require("client_secret")?;
+        let max_redirects = sopts.require_int("max_redirects")?;
+        let report_offset = parse_utc_offset(&sopts.require("report_timezone")?)?;
+        let verify_responses = sopts.flag("verify_responses")?;
+        let manifest = sopts.text("available_endpoints")?;
+
+        let mut fdw = SustainalyticsFdw {
             base_url,
             client_id,
             client_secret,
+            max_redirects,
+            report_offset,
+            verify_responses,
+            available_endpoints: None,
             cached_token: None,
+            http_requests: 0,
+            token_expires_at: None,
+            cached_catalog: None,
+            catalog_version: CatalogVersion::default(),
             scan: ScanState::None,
         };
+        fdw.available_endpoints = match manifest.as_deref() {
+            None => None,
+            Some("probe") => Some(fdw.probe_endpoints()),
+            Some(list) => {
+                let listed: Vec<String> = name_list(list).iter().map(|n| n.to_string()).collect();
+                if let Some(bad) = listed.iter().find(|n| !DATA_ENDPOINTS.contains(&n.as_str())) {
+                    return Err(format!(
+                        "invalid available_endpoints: {} (expected 'probe' or a list of {})",
+                        bad,
+                        DATA_ENDPOINTS.join(", ")
+                    ).into());
+                }
+                Some(listed)
+            }
+        };
 
         *INSTANCE.lock().unwrap() = Some(fdw);
         Ok(())
@@ -310,45 +2565,178 @@ impl ForeignDataWrapper for SustainalyticsFdw {
         let mut guard = INSTANCE.lock().unwrap();
         let fdw = guard.as_mut().ok_or("FDW not initialized")?;
 
-        let topts = ctx.get_options(OptionsType::Table);
-        let endpoint = topts.get("endpoint").ok_or("missing table option endpoint")?.to_string();
+        let raw = ctx.get_options(OptionsType::Table);
+        let topts = TypedOptions::new(OptionScope::Table, &raw);
+        let endpoint = topts.require("endpoint")?;
+        topts.check_endpoint(&endpoint)?;
+        if !fdw.endpoint_available(&endpoint) {
+            return Err(format!(
+                "endpoint {} is not exposed by this gateway (available_endpoints: {})",
+                endpoint,
+                fdw.available_endpoints.as_deref().unwrap_or_default().join(", ")
+            ).into());
+        }
+        topts.validate_all()?;
 
         match endpoint.as_str() {
             "DataServices" => {
-                let allowed = ["endpoint", "ProductId", "PackageIds", "FieldClusterIds", "FieldIds", "Take"];
-                for (k, _) in topts.iter() {
-                    if !allowed.contains(&k.as_str()) {
-                        return Err(format!("unsupported table option for DataServices: {}", k).into());
+                let product_id = topts.require("ProductId")?;
+                let requested_take = topts.require_int("Take")?;
+                let take = SustainalyticsFdw::normalize_take(requested_take);
+                if take < requested_take {
+                    utils::report_warning(&format!(
+                        "{}: Take {} exceeds the maximum of {}; paging with Take={}, which needs about {}x more requests",
+                        FDW_NAME,
+                        requested_take,
+                        MAX_TAKE,
+                        take,
+                        requested_take.saturating_add(take - 1) / take,
+                    ));
+                }
+
+                let mut params = DataServicesParams {
+                    product_id,
+                    package_ids: topts.text("PackageIds")?,
+                    field_cluster_ids: topts.text("FieldClusterIds")?,
+                    field_ids: topts.text("FieldIds")?,
+                    as_of: None,
+                };
+
+                let snapshot_fence_secs = topts.require_int("snapshot_fence_secs")?;
+                let mut backfill_dates = match topts.text("AsOf_start")? {
+                    Some(start) => {
+                        if snapshot_fence_secs > 0 {
+                            return Err("AsOf_start and snapshot_fence_secs can't be combined: a backfill sets AsOf per date".into());
+                        }
+                        let start = parse_date("AsOf_start", &start)?;
+                        let end = parse_date("AsOf_end", &topts.require("AsOf_end")?)?;
+                        Backfill::dates(start, end, &topts.require("AsOf_step")?)?
                     }
+                    None if topts.text("AsOf_end")?.is_some() => return Err("AsOf_end requires AsOf_start".into()),
+                    None => VecDeque::new(),
+                };
+                let first_as_of = backfill_dates.pop_front();
+                if let Some(date) = first_as_of {
+                    params.as_of = Some(format_date(date));
+                } else if snapshot_fence_secs > 0 {
+                    params.as_of = SustainalyticsFdw::fenced_as_of(&params.product_id, snapshot_fence_secs);
                 }
 
-                let product_id = topts.get("ProductId").ok_or("missing required table option ProductId")?.to_string();
-                let take = SustainalyticsFdw::normalize_take(topts.get("Take").map(|s| s.to_string()));
+                if topts.flag("validate_options")? {
+                    fdw.validate_against_catalog(&params)?;
+                }
 
-                let params = DataServicesParams {
-                    product_id,
-                    package_ids: topts.get("PackageIds").map(|s| s.to_string()),
-                    field_cluster_ids: topts.get("FieldClusterIds").map(|s| s.to_string()),
-                    field_ids: topts.get("FieldIds").map(|s| s.to_string()),
-                    take,
+                let entity_ids = SustainalyticsFdw::entity_id_quals(ctx);
+                if let Ok(ids) = &entity_ids {
+                    warn_invalid_ids(ids);
+                }
+                let request_mode = topts.require("request_mode")?;
+                let full_scan = request_mode != "per_entity"
+                    && entity_ids.as_ref().is_ok_and(|ids| ids.is_empty())
+                    && topts.require_int("Skip")? == 0
+                    && first_as_of.is_none();
+                let paging = match request_mode.as_str() {
+                    "per_entity" => {
+                        let ids = entity_ids.map_err(|e| format!("per_entity: {}", e))?;
+                        SustainalyticsFdw::build_entity_pager(&topts, ids)?
+                    }
+                    _ => SustainalyticsFdw::build_pager(&topts, take)?,
+                };
+
+                let mut converter = RowConverter {
+                    columns: ColumnSpec::from_context(ctx),
+                    duplicate_fields: DuplicateFields::parse(&topts.require("duplicate_fields")?),
+                    validate_values: ValueCheck::parse(&topts.require("validate_values")?),
+                    lenient: topts.flag("lenient")?,
+                    report_offset: fdw.report_offset,
+                    as_of: first_as_of,
+                    ..Default::default()
                 };
+                if let Some(spec) = topts.text("transforms")? {
+                    ColumnSpec::apply_transforms(&mut converter.columns, &spec)?;
+                }
+                if let Some(spec) = topts.text("future_columns")? {
+                    warn_future_columns("DataServices", &ColumnSpec::apply_future(&mut converter.columns, &spec));
+                }
+                if converter.validate_values != ValueCheck::Off {
+                    fdw.attach_value_domains(&params.product_id, &mut converter.columns)?;
+                }
 
                 let mut scan = DataServicesScan {
                     params,
-                    skip: 0,
+                    paging,
                     page_rows: vec![],
                     page_idx: 0,
-                    done: false,
+                    converter,
+                    ready: VecDeque::new(),
+                    rows_emitted: 0,
+                    requests: 0,
+                    request_limit: None,
+                    throttle_ms: topts.require_int("throttle_ms")? as u64,
+                    catalog_generation: fdw.catalog_version.generation,
+                    snapshot_fence_secs,
+                    backfill: None,
+                    full_scan,
+                    summary_row: match topts.require("summary_row")?.as_str() {
+                        "true" => SummaryRow::Row,
+                        "notice" => SummaryRow::Notice,
+                        _ => SummaryRow::Off,
+                    },
+                    started_at: time::epoch_secs(),
+                    http_requests_at_start: fdw.http_requests,
+                };
+                if first_as_of.is_some() {
+                    scan.backfill = Some(Backfill { dates: backfill_dates, paging: scan.paging.clone() });
+                }
+
+                scan.request_limit = if topts.flag("allow_large_scan")? {
+                    None
+                } else {
+                    topts.int("confirm_requests_over")?.map(|n| n as u64)
                 };
 
+                // Per-entity scans are sized before any request; paged scans
+                // once the first page reports a total. Scans that can't be
+                // sized are held to the limit as they page.
+                SustainalyticsFdw::check_request_estimate(&scan)?;
                 fdw.load_dataservices_page(&mut scan)?;
-                fdw.scan = ScanState::DataServices(scan);
+                SustainalyticsFdw::check_request_estimate(&scan)?;
+                fdw.scan = ScanState::DataServices(Box::new(scan));
                 Ok(())
             }
 
             "FieldMappingDefinitions" => {
                 let rows = fdw.load_field_mapping_definitions()?;
-                fdw.scan = ScanState::FieldMappingDefinitions(FieldMappingDefinitionsScan { rows, idx: 0 });
+                fdw.cached_catalog = Some(rows.clone());
+                // Listed, selected and unknown to this build; see `ColumnSpec::apply_future`.
+                let spec = topts.text("future_columns")?.unwrap_or_default();
+                let (listed, probe) = (name_list(&spec), FieldMappingRow::default());
+                let future: Vec<String> = ctx
+                    .get_columns()
+                    .iter()
+                    .filter(|c| listed.contains(&c.name()) && probe.cell(c.name(), c.type_oid(), 0, 0).is_none())
+                    .map(|c| c.name().to_string())
+                    .collect();
+                warn_future_columns("FieldMappingDefinitions", &future);
+                fdw.scan = ScanState::FieldMappingDefinitions(FieldMappingDefinitionsScan {
+                    rows,
+                    idx: 0,
+                    future,
+                    fetched_at: time::epoch_secs(),
+                    report_offset: fdw.report_offset,
+                });
+                Ok(())
+            }
+
+            "Options" => {
+                let unavailable = DATA_ENDPOINTS.iter().copied().filter(|e| !fdw.endpoint_available(e)).collect();
+                fdw.scan = ScanState::Options(OptionsScan { idx: 0, unavailable });
+                Ok(())
+            }
+
+            "Health" => {
+                let row = fdw.check_health();
+                fdw.scan = ScanState::Health(HealthScan { row: Some(row), report_offset: fdw.report_offset });
                 Ok(())
             }
 
@@ -360,71 +2748,326 @@ impl ForeignDataWrapper for SustainalyticsFdw {
         let mut guard = INSTANCE.lock().unwrap();
         let fdw = guard.as_mut().ok_or("FDW not initialized")?;
 
-        match &mut fdw.scan {
-            ScanState::DataServices(scan) => {
-                fdw.ensure_dataservices_rows(scan)?;
+        // Take the scan out of the instance so the DataServices arm can call
+        // back into `fdw` for further pages while holding the scan mutably.
+        let mut state = std::mem::take(&mut fdw.scan);
+        let result = match &mut state {
+            ScanState::DataServices(scan) => fdw.iter_dataservices(row, scan),
+            ScanState::FieldMappingDefinitions(scan) => Self::iter_field_mapping_definitions(ctx, row, scan),
+            ScanState::Options(scan) => Self::iter_options(ctx, row, scan),
+            ScanState::Health(scan) => Self::iter_health(ctx, row, scan),
+            ScanState::None => Ok(None),
+        };
+        if let Ok(None) = result {
+            fdw.record_sync(&state);
+        }
+        fdw.scan = state;
+        result
+    }
 
-                if scan.page_idx >= scan.page_rows.len() {
-                    return Ok(None);
-                }
+    fn end_scan(_ctx: &Context) -> FdwResult<()> {
+        let mut guard = INSTANCE.lock().unwrap();
+        if let Some(fdw) = guard.as_mut() {
+            if let ScanState::DataServices(scan) = &fdw.scan {
+                scan.converter.report();
+            }
+            fdw.scan = ScanState::None;
+        }
+        Ok(())
+    }
+}
 
-                let src = &scan.page_rows[scan.page_idx];
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_options_accept_wrapper_package_keys() {
+        let options = [
+            ("fdw_package_url", "https://github.com/smowry40/sustainalytics-wasm-fdw/releases/download/v0.1.0/sustainalytics_wasm_fdw.wasm"),
+            ("fdw_package_name", "smowry:sustainalytics-fdw"),
+            ("fdw_package_version", "0.1.0"),
+            ("fdw_package_checksum", "0123abcd"),
+            ("client_id", "id"),
+            ("client_secret", "secret"),
+        ];
+        assert!(validate_options(OptionScope::Server, options).is_ok());
+        assert!(validate_options(OptionScope::Server, [("client_idd", "id")]).is_err());
+        assert!(validate_options(OptionScope::Table, [("fdw_package_url", "x")]).is_err());
+    }
 
-                for col in ctx.get_columns() {
-                    let cell = match col.name() {
-                        "entityId" => src.get("entityId").map(|v| Cell::String(v.to_string().trim_matches('\"').to_string())),
-                        "entityName" => src.get("entityName").and_then(|v| v.as_str().map(|s| Cell::String(s.to_string()))),
-                        "fields" => src.get("fields").map(|v| Cell::Jsonb(v.to_string())),
-                        other => return Err(format!("unsupported column for DataServices: {}", other).into()),
-                    };
-                    row.push(cell.as_ref());
-                }
+    #[test]
+    fn sha256_matches_fips_180_4_vectors() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
 
-                scan.page_idx += 1;
-                Ok(Some(0))
-            }
+    fn response(headers: &[(&str, &str)], body: &str) -> http::Response {
+        http::Response {
+            url: "https://api.sustainalytics.com/v2/DataService".to_string(),
+            status_code: 200,
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            body: body.to_string(),
+        }
+    }
 
-            ScanState::FieldMappingDefinitions(scan) => {
-                if scan.idx >= scan.rows.len() {
-                    return Ok(None);
-                }
+    #[test]
+    fn verify_body_checks_length_and_checksum() {
+        assert!(verify_body(&response(&[("Content-Length", "3")], "abc")).is_ok());
+        assert!(verify_body(&response(&[("content-length", "10")], "abc")).is_err());
+        assert!(verify_body(&response(&[("Content-Length", "10"), ("Content-Encoding", "gzip")], "abc")).is_ok());
+        assert!(verify_body(&response(&[("Content-Length", "4")], "a\u{FFFD}")).is_ok());
 
-                let r = &scan.rows[scan.idx];
-
-                for col in ctx.get_columns() {
-                    let cell = match col.name() {
-                        "product_id" => Some(Cell::String(r.product_id.clone())),
-                        "product_name" => r.product_name.clone().map(Cell::String),
-                        "package_id" => r.package_id.map(Cell::I64),
-                        "package_name" => r.package_name.clone().map(Cell::String),
-                        "field_cluster_id" => r.field_cluster_id.map(Cell::I64),
-                        "field_cluster_name" => r.field_cluster_name.clone().map(Cell::String),
-                        "field_id" => r.field_id.map(Cell::I64),
-                        "field_name" => r.field_name.clone().map(Cell::String),
-                        "description" => r.description.clone().map(Cell::String),
-                        "field_type" => r.field_type.clone().map(Cell::String),
-                        "field_length" => r.field_length.clone().map(Cell::String),
-                        "possible_values" => r.possible_values.clone().map(Cell::String),
-                        "grouping" => r.grouping.clone().map(Cell::String),
-                        "parentage" => r.parentage.clone().map(|v| Cell::Jsonb(v.to_string())),
-                        other => return Err(format!("unsupported column for FieldMappingDefinitions: {}", other).into()),
-                    };
-                    row.push(cell.as_ref());
-                }
+        let sha = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert!(verify_body(&response(&[("X-Content-SHA256", sha)], "abc")).is_ok());
+        assert!(verify_body(&response(&[("x-content-sha256", sha)], "abd")).is_err());
+    }
 
-                scan.idx += 1;
-                Ok(Some(0))
-            }
+    #[test]
+    fn domain_check_compares_numbers_by_value() {
+        let col = ColumnSpec { domain: parse_possible_values("0,1"), ..column("field_3", TypeOid::I64) };
+        assert!(!RowConverter::out_of_domain(&col, &serde_json::json!(1.0)));
+        assert!(!RowConverter::out_of_domain(&col, &serde_json::json!(0)));
+        assert!(!RowConverter::out_of_domain(&col, &serde_json::json!("1")));
+        assert!(RowConverter::out_of_domain(&col, &serde_json::json!(2)));
+
+        let col = ColumnSpec { domain: parse_possible_values("Low; Medium; High"), ..column("field_4", TypeOid::String) };
+        assert!(!RowConverter::out_of_domain(&col, &serde_json::json!("Medium")));
+        assert!(RowConverter::out_of_domain(&col, &serde_json::json!("medium")));
+    }
 
-            ScanState::None => Ok(None),
+    #[test]
+    fn possible_values_enumerations_become_domains() {
+        assert_eq!(parse_possible_values("Low; Medium; High"), Some(vec!["Low".to_string(), "Medium".to_string(), "High".to_string()]));
+        assert_eq!(parse_possible_values("0,1"), Some(vec!["0".to_string(), "1".to_string()]));
+        assert_eq!(parse_possible_values("Yes | No | Not Applicable").map(|v| v.len()), Some(3));
+        assert_eq!(parse_possible_values("-1, 0, 1").map(|v| v.len()), Some(3));
+    }
+
+    #[test]
+    fn possible_values_descriptions_have_no_domain() {
+        assert_eq!(parse_possible_values("0-100"), None);
+        assert_eq!(parse_possible_values("1 to 5"), None);
+        assert_eq!(parse_possible_values("0..1, 2..3"), None);
+        assert_eq!(parse_possible_values("Free text"), None);
+        assert_eq!(parse_possible_values("Score between 0 and 100, where higher means more risk."), None);
+        assert_eq!(parse_possible_values("Any value reported by the company in its annual filing, or null"), None);
+        assert_eq!(parse_possible_values(""), None);
+    }
+
+    fn page(first: i64, len: i64) -> Vec<JsonValue> {
+        (first..first + len).map(|i| JsonValue::Object([("entityId".to_string(), JsonValue::from(i))].into_iter().collect())).collect()
+    }
+
+    fn skip_take(skip: i64) -> SkipTakePager {
+        SkipTakePager { skip, take: 10, ..Default::default() }
+    }
+
+    #[test]
+    fn skip_take_rejects_implausible_total_count() {
+        let rows = page(0, 10);
+        assert!(skip_take(0).advance(&serde_json::json!({"totalCount": -1}), &rows).is_err());
+        let too_many = serde_json::json!({"totalCount": 100000001});
+        assert!(skip_take(0).advance(&too_many, &rows).is_err());
+
+        let mut pager = skip_take(0);
+        pager.advance(&serde_json::json!({"totalCount": 15}), &rows).unwrap();
+        assert_eq!(pager.skip, 10);
+        assert!(!pager.is_done());
+        assert_eq!(pager.remaining_requests(), Some(1));
+        pager.advance(&serde_json::json!({"totalCount": 15}), &page(10, 10)).unwrap();
+        assert!(pager.is_done());
+    }
+
+    #[test]
+    fn skip_take_fails_on_skip_overflow() {
+        let mut pager = skip_take(i64::MAX - 5);
+        let err = pager.advance(&JsonValue::Null, &page(0, 10)).unwrap_err();
+        assert!(err.to_string().contains("overflowed"));
+    }
+
+    #[test]
+    fn skip_take_stops_when_skip_is_ignored() {
+        let mut pager = skip_take(0);
+        pager.advance(&JsonValue::Null, &page(0, 10)).unwrap();
+        let err = pager.advance(&JsonValue::Null, &page(0, 10)).unwrap_err();
+        assert!(err.to_string().contains("repeats the previous page"));
+
+        let mut pager = skip_take(MAX_TOTAL_ROWS - 5);
+        assert!(pager.advance(&JsonValue::Null, &page(0, 10)).is_err());
+    }
+
+    #[test]
+    fn negative_skip_and_take_are_rejected() {
+        assert!(validate_options(OptionScope::Table, [("Skip", "-1")]).is_err());
+        assert!(validate_options(OptionScope::Table, [("Take", "0")]).is_err());
+        assert!(validate_options(OptionScope::Table, [("Take", "-10")]).is_err());
+        assert!(validate_options(OptionScope::Table, [("Skip", "0"), ("Take", "25")]).is_ok());
+        assert_eq!(SustainalyticsFdw::normalize_take(25), MAX_TAKE);
+        assert_eq!(SustainalyticsFdw::normalize_take(3), 3);
+    }
+
+    #[test]
+    fn keyset_fails_when_cursor_does_not_advance() {
+        let mut pager = KeysetPager { take: 10, key_field: "entityId".to_string(), param: "After".to_string(), ..Default::default() };
+        pager.advance(&JsonValue::Null, &page(0, 10)).unwrap();
+        assert_eq!(pager.last_key.as_deref(), Some("9"));
+        let err = pager.advance(&JsonValue::Null, &page(0, 10)).unwrap_err();
+        assert!(err.to_string().contains("did not advance"));
+    }
+
+    #[test]
+    fn token_fails_on_repeated_token() {
+        let mut pager = TokenPager { take: 10, param: "ContinuationToken".to_string(), field: "continuationToken".to_string(), ..Default::default() };
+        let response = serde_json::json!({"continuationToken": "abc"});
+        pager.advance(&response, &[]).unwrap();
+        assert!(!pager.is_done());
+        assert!(pager.advance(&response, &[]).is_err());
+    }
+
+    fn column(name: &str, type_oid: TypeOid) -> ColumnSpec {
+        ColumnSpec {
+            name: name.to_string(),
+            type_oid,
+            field_id: name.strip_prefix("field_").and_then(|id| id.parse().ok()),
+            domain: None,
+            transform: None,
+            future: false,
         }
     }
 
-    fn end_scan(_ctx: &Context) -> FdwResult<()> {
-        let mut guard = INSTANCE.lock().unwrap();
-        if let Some(fdw) = guard.as_mut() {
-            fdw.scan = ScanState::None;
+    #[test]
+    fn schema_check_covers_mapped_columns_after_the_first_row() {
+        let mut converter = RowConverter {
+            columns: vec![column("entityId", TypeOid::String), column("field_7", TypeOid::String)],
+            ..Default::default()
+        };
+        converter.convert(&serde_json::json!({"entityId": "1", "fields": []})).unwrap();
+        let structured = serde_json::json!({"entityId": "2", "fields": [{"fieldId": 7, "value": {"a": 1}}]});
+        let err = converter.convert(&structured).unwrap_err();
+        assert!(err.to_string().contains("field_7"));
+
+        let mut converter = RowConverter { columns: vec![column("field_7", TypeOid::Jsonb)], ..Default::default() };
+        converter.convert(&serde_json::json!({"fields": [{"fieldId": 7, "value": [1, 2]}]})).unwrap();
+        converter.convert(&serde_json::json!({"fields": [{"fieldId": 7, "value": 3}]})).unwrap();
+    }
+
+    #[test]
+    fn future_columns_skip_built_in_and_mapped_columns() {
+        let mut columns: Vec<ColumnSpec> = DATA_SERVICES_COLUMNS.iter().map(|(n, _)| column(n, TypeOid::String)).collect();
+        columns.push(column("field_7", TypeOid::String));
+        columns.push(column("esg_tier", TypeOid::String));
+        let marked = ColumnSpec::apply_future(&mut columns, "entityName, as_of, field_7, esg_tier");
+        assert_eq!(marked, vec!["esg_tier".to_string()]);
+    }
+
+    #[test]
+    fn long_duplicates_leave_missing_occurrences_null() {
+        let mut converter = RowConverter {
+            columns: vec![column("field_1", TypeOid::I64), column("field_2", TypeOid::I64)],
+            duplicate_fields: DuplicateFields::Long,
+            ..Default::default()
+        };
+        let src = serde_json::json!({"fields": [
+            {"fieldId": 1, "value": 10}, {"fieldId": 2, "value": 20}, {"fieldId": 1, "value": 11}, {"fieldId": 1, "value": 12}
+        ]});
+        let rows = converter.convert(&src).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows.iter().map(|r| r[1].is_some()).collect::<Vec<_>>(), vec![true, false, false]);
+        assert!(rows.iter().all(|r| r[0].is_some()));
+    }
+
+    fn backfill(start: &str, end: &str, step: &str) -> FdwResult<Vec<String>> {
+        let dates = Backfill::dates(parse_date("AsOf_start", start)?, parse_date("AsOf_end", end)?, step)?;
+        Ok(dates.into_iter().map(format_date).collect())
+    }
+
+    #[test]
+    fn backfill_months_clamp_to_month_end() {
+        assert_eq!(backfill("2024-01-31", "2024-04-30", "1m").unwrap(), ["2024-01-31", "2024-02-29", "2024-03-31", "2024-04-30"]);
+        assert_eq!(backfill("2023-01-31", "2023-03-01", "1m").unwrap(), ["2023-01-31", "2023-02-28"]);
+        assert_eq!(backfill("2023-11-30", "2024-03-01", "2m").unwrap(), ["2023-11-30", "2024-01-30"]);
+    }
+
+    #[test]
+    fn backfill_days_and_weeks_cross_leap_days() {
+        assert_eq!(backfill("2024-02-27", "2024-03-01", "1d").unwrap(), ["2024-02-27", "2024-02-28", "2024-02-29", "2024-03-01"]);
+        assert_eq!(backfill("2100-02-22", "2100-03-08", "1w").unwrap(), ["2100-02-22", "2100-03-01", "2100-03-08"]);
+        assert_eq!(backfill("2024-05-01", "2024-05-01", "1d").unwrap(), ["2024-05-01"]);
+    }
+
+    #[test]
+    fn backfill_rejects_bad_ranges_and_steps() {
+        assert!(backfill("2024-02-01", "2024-01-01", "1d").is_err());
+        assert!(backfill("1990-01-01", "2024-12-31", "1d").unwrap_err().to_string().contains("more than"));
+        assert_eq!(backfill("1990-01-01", "2024-12-31", "1m").unwrap().len(), 420);
+        for step in ["", "d", "0d", "-1d", "1y", "1é", "é", "10001d", "9223372036854775807d"] {
+            assert!(backfill("2024-01-01", "2024-12-31", step).is_err(), "step {:?}", step);
         }
-        Ok(())
+        assert!(parse_date("AsOf_start", "2023-02-29").is_err());
+        assert!(parse_date("AsOf_start", "99999999999-01-01").is_err());
+        assert!(parse_date("AsOf_start", "2024-01-99999999999999").is_err());
+    }
+
+    #[test]
+    fn truncate_depth_cuts_deep_containers() {
+        let shallow = serde_json::json!({"a": [1, {"b": "c"}]});
+        assert_eq!(truncate_depth(&shallow, 3), (shallow.clone(), false));
+
+        let (cut, truncated) = truncate_depth(&shallow, 2);
+        assert!(truncated);
+        assert_eq!(cut, serde_json::json!({"a": [1, null]}));
+
+        assert_eq!(truncate_depth(&shallow, 0), (JsonValue::Null, true));
+        assert_eq!(truncate_depth(&serde_json::json!("x"), 0), (serde_json::json!("x"), false));
+    }
+
+    #[test]
+    fn resolve_location_handles_absolute_and_relative_targets() {
+        let current = "https://api.sustainalytics.com/v2/DataService?ProductId=1&Skip=10";
+        assert_eq!(resolve_location(current, "https://cdn.example.com/x"), "https://cdn.example.com/x");
+        assert_eq!(resolve_location(current, "//cdn.example.com/x"), "https://cdn.example.com/x");
+        assert_eq!(resolve_location(current, "/v3/DataService?ProductId=1"), "https://api.sustainalytics.com/v3/DataService?ProductId=1");
+        assert_eq!(resolve_location(current, "DataServices?ProductId=1"), "https://api.sustainalytics.com/v2/DataServices?ProductId=1");
+        assert_eq!(resolve_location(current, "?ProductId=1&Skip=20"), "https://api.sustainalytics.com/v2/DataService?ProductId=1&Skip=20");
+        assert_eq!(resolve_location("https://api.sustainalytics.com", "v2"), "https://api.sustainalytics.com/v2");
+    }
+
+    #[test]
+    fn url_origin_compares_scheme_host_and_port() {
+        let origin = url_origin("https://api.sustainalytics.com/v2/DataService?x=1");
+        assert_eq!(origin, "https://api.sustainalytics.com");
+        assert_eq!(url_origin("HTTPS://API.Sustainalytics.com/v2"), origin);
+        assert_eq!(url_origin("https://api.sustainalytics.com?x=/y"), origin);
+        assert_ne!(url_origin("https://api.sustainalytics.com:8443/v2"), origin);
+        assert_ne!(url_origin("http://api.sustainalytics.com/v2"), origin);
+        assert_ne!(url_origin("https://api.sustainalytics.com.evil.example/v2"), origin);
+        assert_eq!(url_origin(&resolve_location("https://api.sustainalytics.com/a", "//API.sustainalytics.com/b")), origin);
+    }
+
+    #[test]
+    fn utc_offsets_parse_to_seconds_east() {
+        assert_eq!(parse_utc_offset("+02:00").unwrap(), 7_200);
+        assert_eq!(parse_utc_offset("-05:30").unwrap(), -19_800);
+        assert_eq!(parse_utc_offset("Z").unwrap(), 0);
+        assert_eq!(parse_utc_offset(" utc ").unwrap(), 0);
+        assert_eq!(parse_utc_offset("+14:00").unwrap(), 50_400);
+        for bad in ["+15:00", "+02:60", "02:00", "Europe/Paris", "+", "+ab:00"] {
+            assert!(parse_utc_offset(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn rfc3339_renders_wall_clock_time_at_the_offset() {
+        // 2025-01-01T03:00:00Z
+        let instant = days_from_civil(2025, 1, 1) * 86_400 + 3 * 3_600;
+        assert_eq!(format_rfc3339(instant, 0), "2025-01-01T03:00:00Z");
+        assert_eq!(format_rfc3339(instant, -19_800), "2024-12-31T21:30:00-05:30");
+        assert_eq!(format_rfc3339(instant, 7_200), "2025-01-01T05:00:00+02:00");
+        assert_eq!(format_rfc3339(0, -3_600), "1969-12-31T23:00:00-01:00");
     }
 }